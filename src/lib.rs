@@ -15,10 +15,9 @@
 //! ```no_run - expands to a test so is never actually run anyway
 //! use pyo3_testing::{pyo3test, with_py_raises};
 //! ##[pyo3test]
-//! ##[allow(unused_macros)]
 //! ##[pyo3import(py_adders: from adders import addone)]
 //! fn test_raises() {
-//!     with_py_raises!(PyTypeError, { addone.call1(("4",)) });
+//!     with_py_raises!(PyTypeError, { addone!("4") });
 //! }
 //! ```
 //!
@@ -30,9 +29,14 @@
 //!
 //! Details of previous compatible versions are in the readme.
 
+mod pycall;
+mod pyerr;
+mod pyo3fixture;
 mod pyo3test;
 mod withpyraises;
 
+use pycall::{impl_pyo3call, impl_pyo3trycall};
+use pyo3fixture::{impl_pyo3fixture, impl_pyo3fixtures};
 use pyo3test::impl_pyo3test;
 use withpyraises::impl_with_py_raises;
 
@@ -47,6 +51,102 @@ use proc_macro::TokenStream as TokenStream1;
 ///   3. creates a `"call macro"` for each `pyo3function` so you can easily call it,
 ///   4. executes the body of the testcase using an embedded python interpreter.
 ///
+/// Imports are optional, a testcase without any `#[pyo3import(...)]` is simply run with the python
+/// interpreter available.
+///
+/// ## The python token
+///
+/// The python token is available within the testcase as `py`. To make this explicit, or to give
+/// it a different name, take it as an argument of the testcase:
+///
+/// ```no_run - expands to a test so is never actually run anyway
+/// use pyo3::prelude::*;
+/// use pyo3_testing::pyo3test;
+/// ##[pyo3test]
+/// fn test_python_token(gil: Python<'_>) {
+///     let result: isize = gil.eval(c"1 + 1", None, None).unwrap().extract().unwrap();
+///     assert_eq!(result, 2);
+/// }
+/// ```
+///
+/// ## Fixtures
+///
+/// Any other arguments of the testcase are fixtures: each is provided by calling the
+/// [`#[pyo3fixture]`][macro@pyo3fixture] function of the same name, e.g. `fn test(sample: Bound<'_, PyAny>)`
+/// calls `sample(...)`. Each fixture is called once per testcase, after the fixtures it depends on.
+///
+/// ## Parametrised tests with `#[pyo3params(...)]`
+///
+/// The equivalent of `@pytest.mark.parametrize` is
+/// `#[pyo3params(input, expected; (1, 2), (5, 6))]`: the names of the parameters, then a `;` and
+/// then the cases, each with a value for every parameter. (With only one parameter, just list the
+/// values: `#[pyo3params(input; 1, 5)]`.) Each case is expanded into a separate test, so
+/// `cargo test` reports them individually. Like pytest's ids, the tests are named after the values,
+/// e.g. `test_addone::case_1_2`, or give a case a name of its own: `(1, 2), big: (1000, 1001)`.
+/// Attributes before a case only apply to its test, e.g. `#[should_panic] (1, 3)`.
+/// The values are any rust expressions, which are bound to the parameters with `let input = 1;`
+/// just before the body of the testcase. So they can use `py`, the imported functions and the call
+/// macros.
+///
+/// To check that some inputs raise an exception, use `err ExceptionType` as the value. The case
+/// then expects the body of the testcase to raise this exception: the call macros in the body
+/// return the first error rather than panicking, which is checked just like `with_py_raises!`.
+/// (Call macros used inside another macro, e.g. `assert_eq!(addone!(1), 2)`, still panic.) The
+/// body only runs up to the last statement which can raise, i.e. which has a call macro or `?`, so
+/// the error is checked before any assertions. The `err` parameter has no value in that case, so
+/// can only be used after that statement:
+///
+/// ```no_run - expands to a test so is never actually run anyway
+/// use pyo3::prelude::*;
+/// use pyo3_testing::pyo3test;
+/// ##[pyo3test]
+/// ##[pyo3import(py_adders: from adders import addone)]
+/// ##[pyo3params(input, expected; (1, 2), (5, 6), ("x", err PyTypeError))]
+/// fn test_addone() {
+///     let result: isize = addone!(input);
+///     assert_eq!(result, expected);
+/// }
+/// ```
+///
+/// A parameter which is `err` in every case is instead bound to the exception type, for use with
+/// `with_py_raises!`:
+///
+/// ```no_run - expands to a test so is never actually run anyway
+/// use pyo3::prelude::*;
+/// use pyo3_testing::{pyo3test, with_py_raises};
+/// ##[pyo3test]
+/// ##[pyo3import(py_adders: from adders import addone)]
+/// ##[pyo3params(input, expected; ("x", err PyTypeError), (1.5, err PyTypeError))]
+/// fn test_addone_raises() {
+///     with_py_raises!(expected, { addone!(input) });
+/// }
+/// ```
+///
+/// ## Returning a result
+///
+/// The testcase may return a result, e.g. `fn test() -> PyResult<()>`, so that you can use `?`
+/// rather than unwrapping everything. If a `PyResult` is an `Err` the test fails with the python
+/// traceback. Other return types (e.g. `anyhow::Result<()>`) are passed through to the test
+/// harness unchanged.
+///
+/// ## `sys.modules`
+///
+/// All tests share a single python interpreter. To keep them independent of each other, the
+/// modules which the testcase registers in `sys.modules` via `#[pyo3import(...)]`,
+/// `#[pyo3import(file: ...)]` or `#[pyo3code(...)]` are removed again when it finishes (even if it
+/// panics), or the module they replaced is put back. Anything else in `sys.modules`, including
+/// modules which python imports during the test, is left alone, as other tests may be running
+/// at the same time.
+///
+/// ## Isolated tests with `#[pyo3test(isolated)]`
+///
+/// If your modules keep global state, or you want to test what happens when a module is first
+/// initialised, use `#[pyo3test(isolated)]`. The test harness then reruns just this test in a new
+/// process, which has its own, fresh python interpreter, and passes on the result and any output.
+///
+/// Note: pyo3 does not support loading modules into sub-interpreters, and forking the
+/// multi-threaded test harness is not safe, so a new process is the only way to get a fresh
+/// interpreter. Isolated tests are therefore slower and should return `()` or a `Result`.
 ///
 /// ## Specifying the function or module to test with `#[pyo3import(...)]`
 ///
@@ -68,7 +168,61 @@ use proc_macro::TokenStream as TokenStream1;
 ///
 /// ### Note:
 ///
-/// 1. Multiple imports are possible
+/// 1. Multiple imports are possible, either as separate attributes or by importing several names
+///    from one module: `from python_module import function1, function2` (optionally in brackets:
+///    `from python_module import (function1, function2)`)
+/// 1. Both forms accept `as` to bind to a different name, just like in python:
+///    `from python_module import python_function as alias` or `import python_module as alias`.
+///    The call macros are then named after the alias (`alias!()` and `try_alias!()`). This is
+///    required if the python name is a rust keyword, e.g. `from python_module import type as kind`
+/// 1. Submodules (added with `add_submodule`) can be imported using their dotted name, e.g.
+///    `#[pyo3import(package_rustfn: from package.core.maths import python_function)]` or
+///    `#[pyo3import(package_rustfn: import package.core)]`, where `package_rustfn` is the rust
+///    function of the top-level `#[pymodule]`. The package and each submodule are registered in
+///    `sys.modules` under their dotted names (`package`, `package.core`, ...), just like a real
+///    package import, so python code can import them too. The module itself is bound to its last
+///    name (`maths` or `core` in these examples)
+/// 1. Pure python modules, e.g. from the standard library or your virtual environment, are imported
+///    by leaving out the `module_rustfn:`, e.g. `#[pyo3import(from decimal import Decimal)]`. They
+///    are imported as normal and you get the same bindings and call macros as for rust modules
+/// 1. Python source files in your crate, e.g. helpers which sit next to your tests, are imported
+///    by giving their path, relative to the crate root, in place of the `module_rustfn`:
+///    `#[pyo3import(file "tests/py/helpers.py": from helpers import make_callback)]`. The file is
+///    read at compile time, executed as the module `helpers` and registered in `sys.modules`, so
+///    that it does not depend on `PYTHONPATH`. Rust modules are imported first, so python files can
+///    `import` them
+/// 1. `from python_module import *` binds a namespace `python_module_ns` containing everything the
+///    module exports: the names in `__all__`, if it is set, otherwise every public callable. As the
+///    names are only known at runtime, the functions are called with `python_module_ns!(function(...))`
+///    and `try_python_module_ns!(function(...))`, which panic (or return an `AttributeError`) if the
+///    module does not export `function`
+///
+/// ## Inline python modules with `#[pyo3code(...)]`
+///
+/// To define some python directly in the test, add the attribute
+/// `#[pyo3code(name = "module_name", r#"python code"#)]`. The code is executed as a module, which is
+/// registered in `sys.modules` so that you can then import from it with
+/// `#[pyo3import(from module_name import ...)]`. The code is dedented, so you can indent it to match
+/// your rust code. Rust modules (and python files) are imported first, so that the code can import
+/// the module under test:
+///
+/// ```no_run - expands to a test so is never actually run anyway
+/// use pyo3::prelude::*;
+/// use pyo3_testing::pyo3test;
+/// ##[pyo3test]
+/// ##[pyo3import(py_adders: import adders)]
+/// ##[pyo3code(name = "helpers", r#"
+///     from adders import addone
+///
+///     def addtwo(num):
+///         return addone(addone(num))
+/// "#)]
+/// ##[pyo3import(from helpers import addtwo)]
+/// fn test_inline_python() {
+///     let result: isize = addtwo!(1);
+///     assert_eq!(result, 3);
+/// }
+/// ```
 ///
 /// ## "Call macros"
 ///
@@ -78,12 +232,75 @@ use proc_macro::TokenStream as TokenStream1;
 /// `.call2()` method and then `.unwrap().extract().unwrap()` the result.
 ///
 /// ### Note:
-/// 1. The `"call macros"` will accept positional arguments as in the example below OR a tuple
-///    in the form of `python_function!(*args)` - the `*` is important, just as in python
-/// 2. The "Call macros" cannot currently cope with keyword arguments or a mixture of some
-///    positional arguments followed by *args
-/// 3. The macros will `panic!` if an error occurs due to incorrect argument types, missing arguments
+/// 1. The `"call macros"` accept full python call syntax: positional arguments as in the example
+///    below, `*args`, `keyword = value` and `**kwargs`. E.g.
+///    `python_function!(1, *args, step = 2, **kwargs)` - the `*` and `**` are important, just as in
+///    python. `args` can be anything which converts to a python iterable (e.g. a tuple or `Vec`),
+///    `kwargs` anything which converts to a python mapping (e.g. a `HashMap` or `PyDict`). A keyword
+///    given more than once raises a `TypeError`, just as in python
+/// 2. The macros will `panic!` if an error occurs due to incorrect argument types, missing arguments
 ///    etc. - this is designed for use in tests, where panicing is the acceptable and required behaviour
+///    The panic message shows the call, including the `repr` of each argument, followed by the
+///    python traceback, just like a failing pytest.
+///
+/// ### Fallible call macros
+///
+/// For each `python_function` a second macro `try_python_function!()` is also generated. It accepts
+/// exactly the same arguments but returns a `PyResult<T>` instead of panicking, so that you can
+/// test error paths:
+///
+/// ```no_run - expands to a test so is never actually run anyway
+/// use pyo3::{exceptions::PyTypeError, prelude::*};
+/// use pyo3_testing::pyo3test;
+/// ##[pyo3test]
+/// ##[pyo3import(py_adders: from adders import addone)]
+/// fn test_try_call_macro() {
+///     let result: PyResult<isize> = try_addone!("4");
+///     assert!(result.unwrap_err().is_instance_of::<PyTypeError>(py));
+/// }
+/// ```
+///
+/// ### Constructor macros for classes
+///
+/// Mark `#[pyclass]`es with `class` when importing them: `from python_module import class ClassName`.
+/// `ClassName!(...)` then calls the constructor (`__new__`) and returns a `Bound<'py, ClassName>`, so
+/// that you can go on to call methods or read attributes. `try_ClassName!(...)` returns a
+/// `PyResult<Bound<'py, ClassName>>`. If the rust type has a different name to the python class,
+/// give it as a type annotation: `from python_module import class ClassName: RustType`. The class
+/// object itself is not bound to `ClassName`, as that would shadow the rust type, use
+/// `py.get_type::<RustType>()` if you need it.
+///
+/// ```no_run - expands to a test so is never actually run anyway
+/// use pyo3::prelude::*;
+/// use pyo3_testing::pyo3test;
+/// ##[pyclass(name = "Circle")]
+/// struct PyCircle {
+///     ##[pyo3(get)]
+///     radius: f64,
+/// }
+///
+/// ##[pymethods]
+/// impl PyCircle {
+///     ##[new]
+///     fn new(radius: f64) -> Self {
+///         PyCircle { radius }
+///     }
+/// }
+///
+/// ##[pymodule]
+/// ##[pyo3(name = "shapes")]
+/// fn py_shapes(module: &Bound<'_, PyModule>) -> PyResult<()> {
+///     module.add_class::<PyCircle>()?;
+///     Ok(())
+/// }
+///
+/// ##[pyo3test]
+/// ##[pyo3import(py_shapes: from shapes import class Circle: PyCircle)]
+/// fn test_circle() {
+///     let circle = Circle!(2.0);
+///     assert_eq!(circle.borrow().radius, 2.0);
+/// }
+/// ```
 ///
 /// ## Example usage:
 ///
@@ -128,25 +345,157 @@ pub fn pyo3test(attr: TokenStream1, input: TokenStream1) -> TokenStream1 {
     impl_pyo3test(attr.into(), input.into()).into()
 }
 
+/// A proc macro to turn a function into a fixture, which provides a value to any
+/// [`#[pyo3test]`][macro@pyo3test] (or other fixture) with an argument of the same name. This saves
+/// repeating the same setup in multiple tests, just like a pytest fixture.
+///
+/// Just like a testcase, a fixture can take the python token, which is otherwise available as `py`,
+/// and the fixtures it depends on as arguments. Unlike a testcase, it is not wrapped in
+/// `Python::attach` and does not support `#[pyo3import(...)]`, so use `py.import(...)` to access
+/// modules: the modules imported by the testcase are available, as fixtures are called after the
+/// imports.
+///
+/// ### Teardown
+///
+/// The value returned by the fixture is dropped after the testcase completes, so you can use `Drop`
+/// to clean up. Alternatively, register any code which should be run after the testcase with
+/// `teardown!{...}`. This is moved into a closure, which is run once the testcase completes, even
+/// if it panics. Teardowns are run in reverse order, so a fixture is torn down before any fixtures
+/// it depends on.
+///
+/// ### Example:
+///
+/// ```no_run - expands to a test so is never actually run anyway
+/// use pyo3::prelude::*;
+/// use pyo3_testing::{pyo3fixture, pyo3test};
+///
+/// ##[pyo3fixture]
+/// fn tempdir(py: Python<'_>) -> Bound<'_, PyAny> {
+///     let tempdir = py.import("tempfile").unwrap().call_method0("mkdtemp").unwrap();
+///     let path = tempdir.clone();
+///     teardown! {
+///         py.import("shutil").unwrap().call_method1("rmtree", (path,)).unwrap();
+///     }
+///     tempdir
+/// }
+///
+/// ##[pyo3fixture]
+/// fn config_file(py: Python<'_>, tempdir: Bound<'_, PyAny>) -> Bound<'_, PyAny> {
+///     let path = py.import("os.path").unwrap();
+///     path.call_method1("join", (tempdir, "config.toml")).unwrap()
+/// }
+///
+/// ##[pyo3test]
+/// ##[pyo3import(from os.path import dirname, isdir)]
+/// fn test_fixtures(config_file: Bound<'_, PyAny>) {
+///     let parent: String = dirname!(config_file);
+///     let exists: bool = isdir!(parent);
+///     assert!(exists);
+/// }
+/// ```
+///
+/// ### Shared values
+///
+/// Each fixture is called once per testcase, even if the testcase and several other fixtures need
+/// it, and they all get the same value: a testcase taking both `tempdir` and `config_file` in the
+/// example above gets the directory containing the config file. A value which is needed more than
+/// once is cloned, so it must implement `Clone` (cloning a `Bound` just refers to the same python
+/// object again). The fixtures it depends on must also be in scope wherever a fixture is used.
+#[proc_macro_attribute]
+pub fn pyo3fixture(attr: TokenStream1, input: TokenStream1) -> TokenStream1 {
+    impl_pyo3fixture(attr.into(), input.into()).into()
+}
+
+/// Used by `#[pyo3test]`[macro@pyo3test] and the fixtures it takes to call each fixture once, after
+/// the fixtures it depends on, not intended to be used directly.
+#[doc(hidden)]
+#[proc_macro]
+pub fn pyo3fixtures(input: TokenStream1) -> TokenStream1 {
+    impl_pyo3fixtures(input.into()).into()
+}
+
+/// Used by the "call macros" generated by `#[pyo3test]`[macro@pyo3test] to call the python function,
+/// not intended to be used directly.
+#[doc(hidden)]
+#[proc_macro]
+pub fn pyo3call(input: TokenStream1) -> TokenStream1 {
+    impl_pyo3call(input.into()).into()
+}
+
+/// Used by the fallible `try_` "call macros" generated by `#[pyo3test]`[macro@pyo3test] to call the
+/// python function, not intended to be used directly.
+#[doc(hidden)]
+#[proc_macro]
+pub fn pyo3trycall(input: TokenStream1) -> TokenStream1 {
+    impl_pyo3trycall(input.into()).into()
+}
+
+/// Calls a method on a python object, in the same style as the "call macros" generated by
+/// `#[pyo3test]`[macro@pyo3test]: `py_call!(object.method(args, keyword = value))`.
+///
+/// `object` can be any `Bound` python object, e.g. an instance created by a constructor macro.
+///
+/// ## Note:
+///
+/// 1. Full python call syntax is supported: positional arguments, `*args`, `keyword = value` and
+///    `**kwargs`, exactly as for the call macros
+/// 1. The result is extracted into whatever rust type is required, so you may need a type
+///    annotation, e.g. `let area: f64 = py_call!(circle.area());`
+/// 1. The macro will `panic!` if the method raises an exception or the result cannot be extracted,
+///    showing the call and the python traceback. Inside `with_py_raises!`[macro@with_py_raises]
+///    the error is caught instead, just like for the call macros.
+/// 1. A plain function call also works: `py_call!(function(args))`
+///
+/// ## Example usage:
+///
+/// ```no_run - expands to a test so is never actually run anyway
+/// use pyo3::prelude::*;
+/// use pyo3_testing::{py_call, pyo3test};
+/// ##[pyo3test]
+/// ##[pyo3import(py_shapes: from shapes import class Circle: PyCircle)]
+/// fn test_circle_method() {
+///     let circle = Circle!(2.0);
+///     let result: f64 = py_call!(circle.scale(2.0, offset = 1.0));
+///     assert_eq!(result, 5.0);
+/// }
+/// ```
+#[proc_macro]
+pub fn py_call(input: TokenStream1) -> TokenStream1 {
+    impl_pyo3call(input.into()).into()
+}
+
 /// A proc macro to implement the equivalent of [pytest's `with raises`][1] context manager.
 ///
 /// Use like this: `with_py_raises(ExpectedErrType, {code block which should raise error })`
 ///
+/// To also check the error message, add a regular expression, which must match the `str()` of the
+/// exception, just like `pytest.raises(..., match=...)`:
+/// `with_py_raises(ExpectedErrType, match = "regex", {code block which should raise error })`
+///
+/// The pattern is searched for with python's `re.search`, so plain text matches anywhere in the
+/// message, but any regex special characters (e.g. brackets) must be escaped:
+/// `match = r"divide\(1"`. An invalid pattern causes a panic, with python's explanation.
+///
 /// [1]: https://docs.pytest.org/en/latest/getting-started.html#assert-that-a-certain-exception-is-raised
 ///
 /// ## Note:
 ///
-/// 1. The `ExpectedErrType` must be _in scope_ when calling the macro and must implement
-///    `std::from::From<E> for PyErr`
-/// 1. The code inside the block must be valid rust which returns a `PyResult<T>`. Currently it is not
-///    possible to use the autogenerated call macros provided by `#[pyo3test]`[macro@pyo3test].
-///    If you would like to see that feature, please let me know via [github][2]
-/// 1. Add `#[allow(unused_macros)]` to disable the warning that you have imported a python function
-///    but not called the associated macro.
+/// 1. The `ExpectedErrType` must be _in scope_ when calling the macro, or given as a full path
+///    (e.g. `pyo3::exceptions::PyTypeError`), and must implement `std::from::From<E> for PyErr`
+/// 1. To accept any one of several error types, give them as a tuple, just like python's
+///    `except (A, B):` - e.g. `with_py_raises!((PyTypeError, PyValueError), { ... })`
+/// 1. The code inside the block must be valid rust which returns a `PyResult<T>`, OR end with one of
+///    the call or constructor macros generated by `#[pyo3test]`[macro@pyo3test] (e.g.
+///    `addone!("4")`). The macro will automatically be replaced by its fallible `try_` equivalent
+///    so that the error is caught instead of causing a panic.
 /// 1. The code will `panic!` if the incorrect error, or no error, is returned - this is designed for
-///    use in tests, where panicing is the acceptable and required behaviour
-///
-/// [2]: https://github.com/MusicalNinjas/pyo3-testing/issues/3
+///    use in tests, where panicing is the acceptable and required behaviour. The panic message
+///    includes the full python traceback of an incorrect error, or the python `repr` of the value
+///    returned if there was no error (or just its type, if it cannot be converted to python)
+/// 1. If the expected error is raised the test continues, so you can check several error cases in
+///    one test and still make further assertions afterwards
+/// 1. The macro evaluates to the caught `PyErr`, so you can inspect it further, e.g.:
+///    `let error = with_py_raises!(PyValueError, { ... }); error.value(py) ...`
 ///
 /// ## Example usage:
 ///
@@ -154,12 +503,22 @@ pub fn pyo3test(attr: TokenStream1, input: TokenStream1) -> TokenStream1 {
 /// use pyo3::exceptions::PyTypeError;
 /// use pyo3_testing::{pyo3test, with_py_raises};
 /// ##[pyo3test]
-/// ##[allow(unused_macros)]
 /// ##[pyo3import(py_adders: from adders import addone)]
 /// fn test_raises() {
-///     //can't use `let result =` or `addone!()` here as they don't return a `Result`
+///     with_py_raises!(PyTypeError, { addone!("4") });
+/// }
+///
+/// ##[pyo3test]
+/// ##[pyo3import(py_adders: from adders import addone)]
+/// fn test_raises_without_call_macro() {
 ///     with_py_raises!(PyTypeError, { addone.call1(("4",)) });
 /// }
+///
+/// ##[pyo3test]
+/// ##[pyo3import(py_adders: from adders import addone)]
+/// fn test_raises_with_message() {
+///     with_py_raises!(PyTypeError, match = "cannot be interpreted as an integer", { addone!("4") });
+/// }
 /// ```
 #[proc_macro]
 pub fn with_py_raises(input: TokenStream1) -> TokenStream1 {
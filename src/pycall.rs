@@ -0,0 +1,496 @@
+//! All the implementation logic for the "call macros" generated by `#[pyo3test]` and `py_call!`.
+//!
+//! Each generated `macro_rules!` simply forwards its arguments to one of the hidden proc macros
+//! `pyo3call` or `pyo3trycall`, which parse them using python call syntax and build the actual
+//! call. Separated out into this module, using TokenStream2 to allow for unit testing and easier
+//! refactoring.
+
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, ToTokens};
+use syn::{
+    ext::IdentExt,
+    parse::{Parse, ParseStream},
+    parse2, Expr, ExprUnary, Ident, UnOp,
+};
+
+use crate::pyerr::format_exception;
+
+/// The function which is called by the proc macro `pyo3call`.
+/// Takes a TokenStream2 input in the form `function(args)`, parses it as a PyCall and returns the
+/// code required to call the function, unwrap the result and extract it into a rust type.
+pub fn impl_pyo3call(input: TokenStream2) -> TokenStream2 {
+    let pycall: PyCall = match parse2(input) {
+        Ok(pycall) => pycall,
+        Err(e) => return e.into_compile_error(),
+    };
+    expand_unwrap(pycall)
+}
+
+/// The function which is called by the proc macro `pyo3trycall`.
+/// Takes a TokenStream2 input in the form `function(args)`, parses it as a PyCall and returns the
+/// code required to call the function and extract the result into a `PyResult<T>`.
+pub fn impl_pyo3trycall(input: TokenStream2) -> TokenStream2 {
+    let pycall: PyCall = match parse2(input) {
+        Ok(pycall) => pycall,
+        Err(e) => return e.into_compile_error(),
+    };
+    expand_try(pycall)
+}
+
+/// A call to a python function or method, written using python call syntax.
+/// E.g. `addone(1, step = 2)` or `circle.resize(2, keep_centre = true)`
+struct PyCall {
+    /// The rust binding of the python function to call, or of the object for a method call
+    function: Expr,
+    /// The name of the method to call, if this is a method call
+    method: Option<Ident>,
+    /// The arguments, in the order they were given
+    args: Vec<PyCallArg>,
+}
+
+/// A single argument in a python function call.
+enum PyCallArg {
+    /// `value`
+    Positional(Expr),
+    /// `*args` - anything which can be converted into a python iterable
+    Args(Expr),
+    /// `name = value`
+    Keyword(Ident, Expr),
+    /// `**kwargs` - anything which can be converted into a python mapping
+    KwArgs(Expr),
+}
+
+impl Parse for PyCall {
+    /// Python call syntax is also valid rust syntax, so we can parse the call as a rust function call
+    /// and then interpret the arguments: `*args` is a dereference, `**kwargs` a double dereference
+    /// and `name = value` an assignment. Method calls are parsed in the same way.
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let (function, method, callargs) = match input.parse()? {
+            Expr::Call(call) => (*call.func, None, call.args),
+            Expr::MethodCall(call) if call.turbofish.is_none() => {
+                (*call.receiver, Some(call.method), call.args)
+            }
+            other => {
+                return Err(syn::Error::new_spanned(
+                    other,
+                    "expected a python function or method call, e.g. `function(args)` or `object.method(args)`",
+                ))
+            }
+        };
+        let mut args = Vec::<PyCallArg>::new();
+        let mut keywords_given = Vec::<Ident>::new();
+        let mut kwargs_given = false;
+        for arg in callargs {
+            let arg = match arg {
+                Expr::Unary(ExprUnary {
+                    op: UnOp::Deref(_),
+                    expr,
+                    ..
+                }) => match *expr {
+                    Expr::Unary(ExprUnary {
+                        op: UnOp::Deref(_),
+                        expr,
+                        ..
+                    }) => PyCallArg::KwArgs(*expr),
+                    expr => PyCallArg::Args(expr),
+                },
+                Expr::Assign(assign) => match *assign.left {
+                    Expr::Path(path) if path.qself.is_none() && path.path.get_ident().is_some() => {
+                        PyCallArg::Keyword(path.path.get_ident().unwrap().clone(), *assign.right)
+                    }
+                    left => {
+                        return Err(syn::Error::new_spanned(
+                            left,
+                            "invalid keyword argument: expected an identifier before the `=`",
+                        ))
+                    }
+                },
+                value => PyCallArg::Positional(value),
+            };
+            // Follow python's rules on argument order
+            match &arg {
+                PyCallArg::Positional(value) if kwargs_given => {
+                    return Err(syn::Error::new_spanned(
+                        value,
+                        "positional argument follows keyword argument unpacking",
+                    ))
+                }
+                PyCallArg::Positional(value) if !keywords_given.is_empty() => {
+                    return Err(syn::Error::new_spanned(
+                        value,
+                        "positional argument follows keyword argument",
+                    ))
+                }
+                PyCallArg::Args(value) if kwargs_given => {
+                    return Err(syn::Error::new_spanned(
+                        value,
+                        "iterable argument unpacking follows keyword argument unpacking",
+                    ))
+                }
+                PyCallArg::Keyword(keyword, _) if keywords_given.contains(&keyword.unraw()) => {
+                    return Err(syn::Error::new(
+                        keyword.span(),
+                        format!("keyword argument repeated: {}", keyword.unraw()),
+                    ))
+                }
+                PyCallArg::Keyword(keyword, _) => keywords_given.push(keyword.unraw()),
+                PyCallArg::KwArgs(_) => kwargs_given = true,
+                _ => (),
+            }
+            args.push(arg);
+        }
+        Ok(PyCall {
+            function,
+            method,
+            args,
+        })
+    }
+}
+
+/// Idents used in the expanded code. These use mixed_site hygiene so that we can never clash with
+/// any idents used in the arguments.
+struct Bindings {
+    function: Ident,
+    py: Ident,
+    args: Ident,
+    kwargs: Ident,
+}
+
+impl Bindings {
+    fn new() -> Self {
+        Bindings {
+            function: Ident::new("function", Span::mixed_site()),
+            py: Ident::new("py", Span::mixed_site()),
+            args: Ident::new("args", Span::mixed_site()),
+            kwargs: Ident::new("kwargs", Span::mixed_site()),
+        }
+    }
+}
+
+/// Build the python call arguments: positional arguments (including any unpacked `*args`) are
+/// collected into a `PyList`, keyword arguments (including any unpacked `**kwargs`) into a `PyDict`.
+/// Arguments are evaluated in the order they were given, just as in python.
+///
+/// A keyword which is given more than once, e.g. explicitly and in an unpacked `**kwargs`, raises a
+/// `TypeError`, just as in python, rather than one value silently replacing the other.
+///
+/// `on_error` is appended to every fallible step, e.g. `?` or `.unwrap()`.
+fn build_arguments(pycall: PyCall, bindings: &Bindings, on_error: TokenStream2) -> TokenStream2 {
+    let Bindings {
+        function,
+        py,
+        args,
+        kwargs,
+    } = bindings;
+    let functionname = function_name(&pycall);
+    let callable = pycall.function;
+    let arg = Ident::new("arg", Span::mixed_site());
+    let mapping = Ident::new("mapping", Span::mixed_site());
+    let key = Ident::new("key", Span::mixed_site());
+    let repeated = |keyword: TokenStream2| {
+        quote! {
+            if #kwargs.contains(#keyword)#on_error {
+                ::std::result::Result::<(), ::pyo3::PyErr>::Err(
+                    ::pyo3::exceptions::PyTypeError::new_err(format!(
+                        "{}() got multiple values for keyword argument '{}'",
+                        #functionname, #keyword
+                    )),
+                )#on_error;
+            }
+        }
+    };
+    let mut positionals = Vec::<TokenStream2>::new();
+    let mut keywords = Vec::<TokenStream2>::new();
+    let mut kwargs_given = false;
+    for pycallarg in pycall.args {
+        match pycallarg {
+            PyCallArg::Positional(value) => positionals.push(quote! {
+                #args.append(#value)#on_error;
+            }),
+            PyCallArg::Args(value) => positionals.push(quote! {
+                for #arg in ::pyo3::IntoPyObjectExt::into_bound_py_any(#value, #py)#on_error
+                    .try_iter()#on_error
+                {
+                    #args.append(#arg #on_error)#on_error;
+                }
+            }),
+            PyCallArg::Keyword(keyword, value) => {
+                let keyword = keyword.unraw().to_string();
+                // explicit keywords are unique, but may already be in an unpacked `**kwargs`
+                let repeated = if kwargs_given {
+                    repeated(quote! { #keyword })
+                } else {
+                    quote! {}
+                };
+                keywords.push(quote! {
+                    #repeated
+                    #kwargs.set_item(#keyword, #value)#on_error;
+                })
+            }
+            PyCallArg::KwArgs(value) => {
+                kwargs_given = true;
+                let repeated = repeated(quote! { &#key });
+                keywords.push(quote! {
+                    let #mapping = ::pyo3::IntoPyObjectExt::into_bound_py_any(#value, #py)#on_error
+                        .cast_into::<::pyo3::types::PyMapping>()#on_error;
+                    for #key in #mapping.keys()#on_error {
+                        #repeated
+                    }
+                    #kwargs.update(&#mapping)#on_error;
+                })
+            }
+        }
+    }
+    quote! {
+        let #function = &#callable;
+        let #py = #function.py();
+        let #args = ::pyo3::types::PyList::empty(#py);
+        #(#positionals)*
+        let #kwargs = ::pyo3::types::PyDict::new(#py);
+        #(#keywords)*
+    }
+}
+
+/// The name of the function, or method, being called, as written in the call.
+fn function_name(pycall: &PyCall) -> String {
+    let functionname = pycall.function.to_token_stream().to_string();
+    match &pycall.method {
+        None => functionname,
+        Some(method) => functionname + "." + &method.unraw().to_string(),
+    }
+}
+
+/// Build the expression which actually calls the python function, or method, using the arguments
+/// built by `build_arguments`.
+fn build_call(pycall: &PyCall, bindings: &Bindings) -> TokenStream2 {
+    let Bindings {
+        function,
+        args,
+        kwargs,
+        ..
+    } = bindings;
+    match &pycall.method {
+        None => quote! { #function.call(#args.to_tuple(), Some(&#kwargs)) },
+        Some(method) => {
+            let method = method.unraw().to_string();
+            quote! { #function.call_method(#method, #args.to_tuple(), Some(&#kwargs)) }
+        }
+    }
+}
+
+/// Build the fallible python call.
+///
+/// The call is wrapped in a closure which is called immediately, so that any error along the way
+/// can be returned as a `PyResult` via `?`.
+fn expand_try(pycall: PyCall) -> TokenStream2 {
+    let bindings = Bindings::new();
+    let call = build_call(&pycall, &bindings);
+    let arguments = build_arguments(pycall, &bindings, quote! {?});
+    quote! {
+        (|| -> ::pyo3::PyResult<_> {
+            #arguments
+            #call?
+                .extract()
+                .map_err(::std::convert::Into::into)
+        })()
+    }
+}
+
+/// Build the python call, panicking if anything goes wrong.
+///
+/// If the call raises an exception, or the result cannot be extracted, the panic message describes
+/// the call (function name and argument `repr`s) and includes the full python traceback.
+fn expand_unwrap(pycall: PyCall) -> TokenStream2 {
+    let functionname = function_name(&pycall);
+    let bindings = Bindings::new();
+    let pyfunctioncall = build_call(&pycall, &bindings);
+    let arguments = build_arguments(pycall, &bindings, quote! {.unwrap()});
+    let Bindings {
+        py, args, kwargs, ..
+    } = bindings;
+    let result = Ident::new("result", Span::mixed_site());
+    let value = Ident::new("value", Span::mixed_site());
+    let error = Ident::new("error", Span::mixed_site());
+    let traceback = format_exception(&error, &py);
+    let call = quote! {
+        format!(
+            "{}({})",
+            #functionname,
+            #args
+                .iter()
+                .map(|arg| format!("{:?}", arg))
+                .chain(#kwargs.iter().map(|(key, value)| format!("{}={:?}", key, value)))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    };
+    quote! {
+        {
+            #arguments
+            match #pyfunctioncall {
+                Ok(#result) => match #result.extract() {
+                    Ok(#value) => #value,
+                    Err(#error) => {
+                        let #error: ::pyo3::PyErr = #error.into();
+                        panic!(
+                            "{} returned {:?} which could not be extracted:\n\n{}",
+                            #call, #result, #traceback
+                        )
+                    }
+                },
+                Err(#error) => panic!("{} raised an exception:\n\n{}", #call, #traceback),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote::format_ident;
+
+    #[test]
+    fn test_full_python_call_syntax() {
+        let input = quote! { addone(1, *rest, step = 2, **opts) };
+        let expected = quote! {
+            (|| -> ::pyo3::PyResult<_> {
+                let function = &addone;
+                let py = function.py();
+                let args = ::pyo3::types::PyList::empty(py);
+                args.append(1)?;
+                for arg in ::pyo3::IntoPyObjectExt::into_bound_py_any(rest, py)?.try_iter()? {
+                    args.append(arg?)?;
+                }
+                let kwargs = ::pyo3::types::PyDict::new(py);
+                kwargs.set_item("step", 2)?;
+                let mapping = ::pyo3::IntoPyObjectExt::into_bound_py_any(opts, py)?
+                    .cast_into::<::pyo3::types::PyMapping>()?;
+                for key in mapping.keys()? {
+                    if kwargs.contains(&key)? {
+                        ::std::result::Result::<(), ::pyo3::PyErr>::Err(
+                            ::pyo3::exceptions::PyTypeError::new_err(format!(
+                                "{}() got multiple values for keyword argument '{}'",
+                                "addone", &key
+                            )),
+                        )?;
+                    }
+                }
+                kwargs.update(&mapping)?;
+                function
+                    .call(args.to_tuple(), Some(&kwargs))?
+                    .extract()
+                    .map_err(::std::convert::Into::into)
+            })()
+        };
+        assert_eq!(impl_pyo3trycall(input).to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn test_unwrap() {
+        let input = quote! { zero() };
+        let traceback = format_exception(&format_ident!("error"), &format_ident!("py"));
+        let expected = quote! {
+            {
+                let function = &zero;
+                let py = function.py();
+                let args = ::pyo3::types::PyList::empty(py);
+                let kwargs = ::pyo3::types::PyDict::new(py);
+                match function.call(args.to_tuple(), Some(&kwargs)) {
+                    Ok(result) => match result.extract() {
+                        Ok(value) => value,
+                        Err(error) => {
+                            let error: ::pyo3::PyErr = error.into();
+                            panic!(
+                                "{} returned {:?} which could not be extracted:\n\n{}",
+                                format!(
+                                    "{}({})",
+                                    "zero",
+                                    args
+                                        .iter()
+                                        .map(|arg| format!("{:?}", arg))
+                                        .chain(kwargs.iter().map(|(key, value)| format!("{}={:?}", key, value)))
+                                        .collect::<Vec<String>>()
+                                        .join(", ")
+                                ),
+                                result,
+                                #traceback
+                            )
+                        }
+                    },
+                    Err(error) => panic!(
+                        "{} raised an exception:\n\n{}",
+                        format!(
+                            "{}({})",
+                            "zero",
+                            args
+                                .iter()
+                                .map(|arg| format!("{:?}", arg))
+                                .chain(kwargs.iter().map(|(key, value)| format!("{}={:?}", key, value)))
+                                .collect::<Vec<String>>()
+                                .join(", ")
+                        ),
+                        #traceback
+                    ),
+                }
+            }
+        };
+        assert_eq!(impl_pyo3call(input).to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn test_method_call() {
+        let input = quote! { circle.scale(2, r#type = "x") };
+        let expected = quote! {
+            (|| -> ::pyo3::PyResult<_> {
+                let function = &circle;
+                let py = function.py();
+                let args = ::pyo3::types::PyList::empty(py);
+                args.append(2)?;
+                let kwargs = ::pyo3::types::PyDict::new(py);
+                kwargs.set_item("type", "x")?;
+                function
+                    .call_method("scale", args.to_tuple(), Some(&kwargs))?
+                    .extract()
+                    .map_err(::std::convert::Into::into)
+            })()
+        };
+        assert_eq!(impl_pyo3trycall(input).to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn test_not_a_call() {
+        let input = quote! { circle.radius };
+        let error = parse2::<PyCall>(input).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "expected a python function or method call, e.g. `function(args)` or `object.method(args)`"
+        );
+    }
+
+    #[test]
+    fn test_positional_after_keyword() {
+        let input = quote! { addone(step = 2, 1) };
+        let error = parse2::<PyCall>(input).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "positional argument follows keyword argument"
+        );
+    }
+
+    #[test]
+    fn test_repeated_keyword() {
+        let input = quote! { addone(step = 1, r#step = 2) };
+        let error = parse2::<PyCall>(input).err().unwrap();
+        assert_eq!(error.to_string(), "keyword argument repeated: step");
+    }
+
+    #[test]
+    fn test_star_args_after_kwargs() {
+        let input = quote! { addone(**opts, *rest) };
+        let error = parse2::<PyCall>(input).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "iterable argument unpacking follows keyword argument unpacking"
+        );
+    }
+}
@@ -0,0 +1,27 @@
+//! Code generation shared by all the macros which need to report a python exception.
+//!
+//! As this is a proc-macro crate we cannot provide any runtime helpers, so everything here returns
+//! a TokenStream2 which is inserted into the expanded code.
+
+use proc_macro2::{Ident, TokenStream as TokenStream2};
+use quote::quote;
+
+/// Returns an expression which renders the `PyErr` bound to `error` to a `String`, exactly as python
+/// would print it: the traceback (if any) followed by the exception type and message.
+///
+/// Uses `traceback.format_exception` and falls back to the rust `Display` of the error if that
+/// fails for any reason, so the expression itself never panics.
+pub fn format_exception(error: &Ident, py: &Ident) -> TokenStream2 {
+    quote! {
+        ::pyo3::types::PyModule::import(#py, "traceback")
+            .and_then(|traceback| {
+                traceback.call_method1(
+                    "format_exception",
+                    (#error.get_type(#py), #error.value(#py), #error.traceback(#py)),
+                )
+            })
+            .and_then(|lines| lines.extract::<Vec<String>>())
+            .map(|lines| lines.concat())
+            .unwrap_or_else(|_| #error.to_string())
+    }
+}
@@ -0,0 +1,507 @@
+//! All the implementation logic for `#[pyo3fixture]`, along with the code needed by `#[pyo3test]`
+//! to resolve the fixtures requested as arguments to a testcase.
+//!
+//! A fixture `fn name(py: Python<'_>, other: Other) -> T` is rewritten to
+//! `fn name<'py>(py: Python<'py>, teardowns: &mut Vec<Box<dyn FnOnce() + 'py>>, other: Other) -> T`
+//! along with a macro `name!`, which tells the testcase that it depends on `other`. The testcase
+//! calls each fixture it needs once, after the fixtures it depends on, and passes the results on.
+//! Any teardown code is pushed onto `teardowns`, to be run by the testcase once it completes.
+
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    braced, bracketed,
+    parse::{Parse, ParseStream},
+    parse2, parse_quote,
+    punctuated::Punctuated,
+    visit_mut::{self, VisitMut},
+    FnArg, GenericArgument, Ident, ItemFn, Lifetime, Pat, PatIdent, PatType, PathArguments,
+    Signature, Token, Type,
+};
+
+/// The function which is called by the proc macro `pyo3fixture`.
+///
+/// The parsing is fallible as the fixture may be incorrectly constructed. In case of a parsing error
+/// this will be converted to a compile error and returned.
+pub fn impl_pyo3fixture(attr: TokenStream2, input: TokenStream2) -> TokenStream2 {
+    if !attr.is_empty() {
+        return syn::Error::new_spanned(attr, "#[pyo3fixture] does not take any options")
+            .into_compile_error();
+    }
+    match parse2::<ItemFn>(input).and_then(wrap_fixture) {
+        Ok(fixture) => fixture,
+        Err(e) => e.into_compile_error(),
+    }
+}
+
+/// A fixture requested as an argument, e.g. `sample: Bound<'_, PyAny>`, which is provided by
+/// calling the `#[pyo3fixture]` function with the same name.
+pub struct Fixture {
+    /// The name of the argument, including any `ref` or `mut`
+    pat: PatIdent,
+    ty: Type,
+}
+
+/// Splits the arguments of a testcase or fixture into the python token (if given) and the fixtures
+/// which it needs.
+pub fn fixture_arguments(
+    signature: &Signature,
+    attribute: &str,
+) -> syn::Result<(Option<Ident>, Vec<Fixture>)> {
+    let error = format!(
+        "{attribute} can only take the python token and fixtures as arguments, e.g. `fn {}(py: Python<'_>, fixture: Bound<'_, PyAny>)`",
+        signature.ident
+    );
+    let mut py = None;
+    let mut fixtures = Vec::<Fixture>::new();
+    for input in &signature.inputs {
+        let FnArg::Typed(PatType { pat, ty, .. }) = input else {
+            return Err(syn::Error::new_spanned(input, error));
+        };
+        let Pat::Ident(pat) = pat.as_ref() else {
+            return Err(syn::Error::new_spanned(input, error));
+        };
+        if python_lifetime(ty).is_some() {
+            if py.is_some() {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    format!("{attribute} can only take one python token"),
+                ));
+            }
+            py = Some(pat.ident.clone());
+        } else {
+            fixtures.push(Fixture {
+                pat: pat.clone(),
+                ty: ty.as_ref().clone(),
+            });
+        }
+    }
+    Ok((py, fixtures))
+}
+
+/// If the type is the python token, returns its lifetime (`'_` if elided)
+fn python_lifetime(ty: &Type) -> Option<Lifetime> {
+    let Type::Path(ty) = ty else {
+        return None;
+    };
+    let segment = ty.path.segments.last()?;
+    if segment.ident != "Python" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) => match arguments.args.first() {
+            Some(GenericArgument::Lifetime(lifetime)) => Some(lifetime.clone()),
+            _ => Some(parse_quote!('_)),
+        },
+        _ => Some(parse_quote!('_)),
+    }
+}
+
+/// Returns the statements needed by a testcase to call its fixtures and then run all the teardowns,
+/// in reverse order, once the testcase completes - even if it panics.
+///
+/// The testcase only knows the names of the fixtures it takes, not what they depend on, so the
+/// fixtures are resolved by `pyo3fixtures!`, which asks each fixture for its dependencies in turn.
+pub fn use_fixtures(fixtures: &[Fixture], py: &Ident) -> TokenStream2 {
+    if fixtures.is_empty() {
+        return quote! {};
+    }
+    let teardowns = Ident::new("teardowns", Span::mixed_site());
+    let pats = fixtures.iter().map(|fixture| &fixture.pat);
+    let types = fixtures.iter().map(|fixture| &fixture.ty);
+    quote! {
+        struct Teardowns<'py>(::std::vec::Vec<::std::boxed::Box<dyn FnOnce() + 'py>>);
+        impl Drop for Teardowns<'_> {
+            fn drop(&mut self) {
+                // a panicking teardown must not stop the others from running, so re-raise the
+                // first panic once they are all done
+                let mut panic = None;
+                // last in, first out: fixtures are torn down before the fixtures they depend on
+                while let Some(teardown) = self.0.pop() {
+                    if let Err(error) =
+                        ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(teardown))
+                    {
+                        panic.get_or_insert(error);
+                    }
+                }
+                // a second panic while already unwinding would abort the test harness
+                if let Some(panic) = panic.filter(|_| !::std::thread::panicking()) {
+                    ::std::panic::resume_unwind(panic);
+                }
+            }
+        }
+        let mut #teardowns = Teardowns(::std::vec::Vec::new());
+
+        ::pyo3_testing::pyo3fixtures! { { #py #teardowns; #(#pats: #types),* } }
+    }
+}
+
+/// The function which is called by the proc macro `pyo3fixtures`, which resolves the fixtures
+/// needed by a testcase.
+///
+/// `pyo3fixtures! { { py teardowns; name: Type, ... } fixture [dependency, ...] ... }` is first
+/// called by the testcase, with the fixtures it takes as arguments. Each fixture generates a macro
+/// with the same name, which adds the fixture and its dependencies to the list and calls
+/// `pyo3fixtures!` again. Once all the fixtures are known, each is called once, after the fixtures
+/// it depends on, and the result is bound to the testcase's arguments.
+pub fn impl_pyo3fixtures(input: TokenStream2) -> TokenStream2 {
+    let resolution = match parse2::<Resolution>(input.clone()) {
+        Ok(resolution) => resolution,
+        Err(e) => return e.into_compile_error(),
+    };
+    match resolution.order() {
+        Ok(Ok(order)) => resolution.call_fixtures(&order),
+        // ask the fixture for its dependencies
+        Ok(Err(unknown)) => quote! { #unknown! { #input } },
+        Err(e) => e.into_compile_error(),
+    }
+}
+
+/// The fixtures taken by a testcase and the dependencies of all the fixtures resolved so far
+struct Resolution {
+    py: Ident,
+    teardowns: Ident,
+    fixtures: Vec<Fixture>,
+    dependencies: Vec<(Ident, Vec<Ident>)>,
+}
+
+impl Parse for Resolution {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let testcase;
+        braced!(testcase in input);
+        let py = testcase.parse()?;
+        let teardowns = testcase.parse()?;
+        testcase.parse::<Token![;]>()?;
+        let mut fixtures = Vec::new();
+        while !testcase.is_empty() {
+            let Pat::Ident(pat) = Pat::parse_single(&testcase)? else {
+                return Err(testcase.error("expected the name of a fixture"));
+            };
+            testcase.parse::<Token![:]>()?;
+            let ty = testcase.parse()?;
+            fixtures.push(Fixture { pat, ty });
+            if !testcase.is_empty() {
+                testcase.parse::<Token![,]>()?;
+            }
+        }
+        let mut dependencies = Vec::new();
+        while !input.is_empty() {
+            let name = input.parse()?;
+            let fixture;
+            bracketed!(fixture in input);
+            let names = Punctuated::<Ident, Token![,]>::parse_terminated(&fixture)?;
+            dependencies.push((name, names.into_iter().collect()));
+        }
+        Ok(Resolution {
+            py,
+            teardowns,
+            fixtures,
+            dependencies,
+        })
+    }
+}
+
+impl Resolution {
+    fn dependencies(&self, name: &Ident) -> Option<&[Ident]> {
+        self.dependencies
+            .iter()
+            .find(|(fixture, _)| fixture == name)
+            .map(|(_, dependencies)| dependencies.as_slice())
+    }
+
+    /// The order in which to call the fixtures, so that each comes after the fixtures it depends
+    /// on, or the first fixture whose dependencies are not yet known.
+    fn order(&self) -> syn::Result<Result<Vec<Ident>, Ident>> {
+        let mut order = Vec::new();
+        let mut resolving = Vec::new();
+        for fixture in &self.fixtures {
+            if let Err(unknown) = self.visit(&fixture.pat.ident, &mut resolving, &mut order)? {
+                return Ok(Err(unknown));
+            }
+        }
+        Ok(Ok(order))
+    }
+
+    fn visit(
+        &self,
+        name: &Ident,
+        resolving: &mut Vec<Ident>,
+        order: &mut Vec<Ident>,
+    ) -> syn::Result<Result<(), Ident>> {
+        if order.contains(name) {
+            return Ok(Ok(()));
+        }
+        if resolving.contains(name) {
+            let cycle: Vec<String> = resolving
+                .iter()
+                .skip_while(|fixture| *fixture != name)
+                .chain([name])
+                .map(Ident::to_string)
+                .collect();
+            return Err(syn::Error::new(
+                name.span(),
+                format!(
+                    "Fixtures cannot depend on themselves: {}",
+                    cycle.join(" -> ")
+                ),
+            ));
+        }
+        let Some(dependencies) = self.dependencies(name) else {
+            return Ok(Err(name.clone()));
+        };
+        resolving.push(name.clone());
+        for dependency in dependencies {
+            if let Err(unknown) = self.visit(dependency, resolving, order)? {
+                return Ok(Err(unknown));
+            }
+        }
+        resolving.pop();
+        order.push(name.clone());
+        Ok(Ok(()))
+    }
+
+    /// Calls each fixture once, in order, and binds the results to the testcase's arguments. A value
+    /// which is needed more than once is cloned, except for the last use.
+    fn call_fixtures(&self, order: &[Ident]) -> TokenStream2 {
+        let value = |name: &Ident| Ident::new(&name.to_string(), Span::mixed_site());
+        let mut uses: Vec<&Ident> = order
+            .iter()
+            .flat_map(|name| self.dependencies(name).unwrap_or_default())
+            .chain(self.fixtures.iter().map(|fixture| &fixture.pat.ident))
+            .collect();
+        let mut take = |name: &Ident| {
+            let position = uses.iter().position(|used| *used == name).unwrap();
+            uses.remove(position);
+            let value = value(name);
+            if uses.contains(&name) {
+                quote! { ::std::clone::Clone::clone(&#value) }
+            } else {
+                quote! { #value }
+            }
+        };
+        let py = &self.py;
+        let teardowns = &self.teardowns;
+        let calls: Vec<TokenStream2> = order
+            .iter()
+            .map(|name| {
+                let arguments = self
+                    .dependencies(name)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(&mut take)
+                    .collect::<Vec<_>>();
+                let value = value(name);
+                quote! { let #value = #name(#py, &mut #teardowns.0 #(, #arguments)*); }
+            })
+            .collect();
+        let bindings = self.fixtures.iter().map(|Fixture { pat, ty }| {
+            let value = take(&pat.ident);
+            quote! { let #pat: #ty = #value; }
+        });
+        quote! {
+            #(#calls)*
+            #(#bindings)*
+        }
+    }
+}
+
+/// Rewrites the fixture to take the python token, the list of teardowns and then the fixtures it
+/// depends on, and provide a `teardown!{...}` macro to register teardown code. Also generates the
+/// macro used by `pyo3fixtures!` to find these dependencies.
+fn wrap_fixture(mut fixture: ItemFn) -> syn::Result<TokenStream2> {
+    let (py, dependencies) = fixture_arguments(&fixture.sig, "#[pyo3fixture]")?;
+    let lifetime = fixture
+        .sig
+        .inputs
+        .iter()
+        .find_map(|input| match input {
+            FnArg::Typed(PatType { ty, .. }) => python_lifetime(ty),
+            FnArg::Receiver(_) => None,
+        })
+        .filter(|lifetime| lifetime.ident != "_");
+    let lifetime = match lifetime {
+        Some(lifetime) => lifetime,
+        None => {
+            // the returned value will usually be bound to the python token, so `'_` must refer
+            // to its lifetime
+            let lifetime: Lifetime = parse_quote!('py);
+            ElidedLifetimes(&lifetime).visit_signature_mut(&mut fixture.sig);
+            fixture
+                .sig
+                .generics
+                .params
+                .insert(0, parse_quote!(#lifetime));
+            lifetime
+        }
+    };
+    let arguments: Vec<FnArg> = fixture
+        .sig
+        .inputs
+        .iter()
+        .filter(|input| match input {
+            FnArg::Typed(PatType { ty, .. }) => python_lifetime(ty).is_none(),
+            FnArg::Receiver(_) => true,
+        })
+        .map(|input| {
+            let mut input = input.clone();
+            ElidedLifetimes(&lifetime).visit_fn_arg_mut(&mut input);
+            input
+        })
+        .collect();
+
+    // `py` is available in the fixture, just like in a testcase, if it is not given explicitly
+    let (py, use_py) = match py {
+        Some(py) => (py, quote! {}),
+        None => {
+            let py = format_ident!("py");
+            (py.clone(), quote! { let _ = #py; })
+        }
+    };
+    let teardowns = Ident::new("teardowns", Span::mixed_site());
+    let statements = &fixture.block.stmts;
+
+    fixture.sig.inputs = parse_quote! {
+        #py: ::pyo3::Python<#lifetime>,
+        #teardowns: &mut ::std::vec::Vec<::std::boxed::Box<dyn FnOnce() + #lifetime>>,
+        #(#arguments),*
+    };
+    fixture.block = parse_quote!({
+        #use_py
+        let _ = &#teardowns;
+
+        // register code to run once the testcase completes
+        #[allow(unused_macros)]
+        macro_rules! teardown {
+            ($($teardown:tt)*) => {
+                #teardowns.push(::std::boxed::Box::new(move || { $($teardown)* }))
+            };
+        }
+
+        #(#statements)*
+    });
+
+    // the macro is imported along with the function, so it must have the same name, but cannot be
+    // defined with it, as `use` would then also import the function again
+    let name = &fixture.sig.ident;
+    let dependencies = dependencies.iter().map(|dependency| &dependency.pat.ident);
+    let resolve = format_ident!("__pyo3fixture_{}", name);
+    Ok(quote! {
+        #fixture
+
+        #[doc(hidden)]
+        #[allow(unused_macros)]
+        macro_rules! #resolve {
+            ($testcase:tt $($fixtures:tt)*) => {
+                ::pyo3_testing::pyo3fixtures! { $testcase #name [#(#dependencies),*] $($fixtures)* }
+            };
+        }
+        #[allow(unused_imports)]
+        pub(crate) use #resolve as #name;
+    })
+}
+
+/// Replaces elided lifetimes (`'_`) with the given lifetime
+struct ElidedLifetimes<'a>(&'a Lifetime);
+
+impl VisitMut for ElidedLifetimes<'_> {
+    fn visit_lifetime_mut(&mut self, lifetime: &mut Lifetime) {
+        if lifetime.ident == "_" {
+            *lifetime = self.0.clone();
+        }
+        visit_mut::visit_lifetime_mut(self, lifetime);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::ToTokens;
+
+    use super::*;
+
+    #[test]
+    fn test_fixture_arguments() {
+        let signature: Signature =
+            parse_quote! { fn test(sample: Bound<'_, PyAny>, gil: Python<'_>, count: usize) };
+        let (py, fixtures) = fixture_arguments(&signature, "#[pyo3test]").unwrap();
+        assert_eq!(py.unwrap(), "gil");
+        let names: Vec<String> = fixtures.iter().map(|f| f.pat.ident.to_string()).collect();
+        assert_eq!(names, ["sample", "count"]);
+    }
+
+    #[test]
+    fn test_wrap_fixture() {
+        let fixture: ItemFn = parse_quote! {
+            fn config(py: Python<'_>, sample: Bound<'_, PyAny>) -> Bound<'_, PyAny> {
+                sample
+            }
+        };
+        let wrapped: syn::File = parse2(wrap_fixture(fixture).unwrap()).unwrap();
+        let syn::Item::Fn(wrapped_fn) = &wrapped.items[0] else {
+            panic!("Expected the fixture function first")
+        };
+        let expected: Signature = parse_quote! {
+            fn config<'py>(
+                py: ::pyo3::Python<'py>,
+                teardowns: &mut ::std::vec::Vec<::std::boxed::Box<dyn FnOnce() + 'py>>,
+                sample: Bound<'py, PyAny>
+            ) -> Bound<'py, PyAny>
+        };
+        assert_eq!(
+            wrapped_fn.sig.to_token_stream().to_string(),
+            expected.to_token_stream().to_string()
+        );
+        let resolve: TokenStream2 = quote! {
+            #[doc(hidden)]
+            #[allow(unused_macros)]
+            macro_rules! __pyo3fixture_config {
+                ($testcase:tt $($fixtures:tt)*) => {
+                    ::pyo3_testing::pyo3fixtures! { $testcase config [sample] $($fixtures)* }
+                };
+            }
+            #[allow(unused_imports)]
+            pub(crate) use __pyo3fixture_config as config;
+        };
+        let items = &wrapped.items[1..];
+        assert_eq!(quote! { #(#items)* }.to_string(), resolve.to_string());
+    }
+
+    #[test]
+    fn test_resolve_unknown_fixture() {
+        let input = quote! { { py teardowns; config: Bound<'_, PyAny> } config [sample] };
+        let expected = quote! {
+            sample! { { py teardowns; config: Bound<'_, PyAny> } config [sample] }
+        };
+        assert_eq!(impl_pyo3fixtures(input).to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn test_resolve_fixtures() {
+        // `sample` is needed by `config`, `other` and the testcase, so is cloned for all but the
+        // last of these
+        let input = quote! {
+            { py teardowns; config: Bound<'_, PyAny>, mut sample: Bound<'_, PyAny> }
+            other [sample] config [sample, other] sample []
+        };
+        let expected = quote! {
+            let sample = sample(py, &mut teardowns.0);
+            let other = other(py, &mut teardowns.0, ::std::clone::Clone::clone(&sample));
+            let config = config(py, &mut teardowns.0, ::std::clone::Clone::clone(&sample), other);
+            let config: Bound<'_, PyAny> = config;
+            let mut sample: Bound<'_, PyAny> = sample;
+        };
+        assert_eq!(impl_pyo3fixtures(input).to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn test_resolve_cycle() {
+        let input = quote! {
+            { py teardowns; chicken: Bound<'_, PyAny> } egg [chicken] chicken [egg]
+        };
+        let resolution: Resolution = parse2(input).unwrap();
+        let error = resolution.order().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Fixtures cannot depend on themselves: chicken -> egg -> chicken"
+        );
+    }
+}
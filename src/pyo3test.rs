@@ -5,13 +5,24 @@
 
 use std::fmt::Debug;
 
-use proc_macro2::{Span, TokenStream as TokenStream2};
-use quote::{format_ident, ToTokens};
+use proc_macro2::{Span, TokenStream as TokenStream2, TokenTree};
+use quote::{format_ident, quote, ToTokens};
 use syn::{
+    ext::IdentExt,
+    parenthesized,
     parse::{Parse, ParseStream},
     parse2, parse_quote,
-    token::Colon,
-    Attribute, Ident, ItemFn, Signature, Stmt, Token,
+    punctuated::Punctuated,
+    token::{Colon, Comma, Paren},
+    visit_mut::{self, VisitMut},
+    Attribute, Expr, GenericArgument, Ident, ItemFn, Lit, LitStr, Macro, Path, PathArguments,
+    ReturnType, Signature, Stmt, Token, Type,
+};
+
+use crate::{
+    pyerr::format_exception,
+    pyo3fixture::{fixture_arguments, use_fixtures, Fixture},
+    withpyraises::impl_with_py_raises,
 };
 
 /// The function which is called by the proc macro `pyo3test`.
@@ -20,36 +31,92 @@ use syn::{
 ///
 /// The parsing is fallible as the testcase or attributes may be incorrectly constructed. In case of
 /// a parsing error this will be converted to a compile error and returned.
-pub fn impl_pyo3test(_attr: TokenStream2, input: TokenStream2) -> TokenStream2 {
-    let testcase: Pyo3TestCase = match parse2::<ItemFn>(input).and_then(|itemfn| itemfn.try_into())
-    {
-        Ok(testcase) => testcase,
+pub fn impl_pyo3test(attr: TokenStream2, input: TokenStream2) -> TokenStream2 {
+    let options: Pyo3TestOptions = match parse2(attr) {
+        Ok(options) => options,
         Err(e) => return e.into_compile_error(),
     };
+    let mut testcase: Pyo3TestCase =
+        match parse2::<ItemFn>(input).and_then(|itemfn| itemfn.try_into()) {
+            Ok(testcase) => testcase,
+            Err(e) => return e.into_compile_error(),
+        };
+    testcase.isolated = options.isolated;
     wrap_testcase(testcase)
 }
 
-/// A pyo3 test case consisting of zero or more imports and an ItemFn which should be wrapped to
-/// execute in Python::with_gil. Don't construct this directly but use .try_into() on a suitable ItemFn
+syn::custom_keyword!(isolated);
+
+/// The options given to the attribute itself: `#[pyo3test]` or `#[pyo3test(isolated)]`
+#[derive(Debug, Default, PartialEq)]
+struct Pyo3TestOptions {
+    isolated: bool,
+}
+
+impl Parse for Pyo3TestOptions {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut options = Pyo3TestOptions::default();
+        if input.is_empty() {
+            return Ok(options);
+        }
+        input
+            .parse::<isolated>()
+            .map_err(|e| syn::Error::new(e.span(), "expected `isolated` or no options"))?;
+        options.isolated = true;
+        if !input.is_empty() {
+            return Err(input.error("unexpected tokens after `isolated`"));
+        }
+        Ok(options)
+    }
+}
+
+/// A pyo3 test case consisting of zero or more imports, zero or more inline python modules and an
+/// ItemFn which should be wrapped to execute in Python::with_gil. Don't construct this directly but
+/// use .try_into() on a suitable ItemFn
 // #[derive(Debug, PartialEq)] - Signature, Stmt, Attribute don't allow either Debug or PartialEq currently.
 struct Pyo3TestCase {
     pyo3imports: Vec<Pyo3Import>,
+    pyo3codes: Vec<Pyo3Code>,
+    /// The cases to expand a parametrised testcase into
+    pyo3params: Option<Pyo3Params>,
+    /// The name to bind the python token to, `py` unless given as an argument to the testcase
+    py: Ident,
+    /// The fixtures requested as arguments to the testcase
+    fixtures: Vec<Fixture>,
     signature: Signature,
     statements: Vec<Stmt>,
     otherattributes: Vec<Attribute>,
+    /// Run the testcase in its own process, with a fresh python interpreter
+    isolated: bool,
 }
 
 /// Attempt to convert an ItemFn into a Pyo3TestCase. This is a fallible conversion as the arguments
-/// provided to a Pyo3Import Attribute may be empty.
+/// provided to a Pyo3Import Attribute may be empty, and the testcase may only take the python token
+/// and fixtures as arguments.
 impl TryFrom<ItemFn> for Pyo3TestCase {
     type Error = syn::Error;
 
-    fn try_from(testcase: ItemFn) -> syn::Result<Pyo3TestCase> {
+    fn try_from(mut testcase: ItemFn) -> syn::Result<Pyo3TestCase> {
+        let (py, fixtures) = fixture_arguments(&testcase.sig, "#[pyo3test]")?;
+        let py = py.unwrap_or_else(|| format_ident!("py"));
+        testcase.sig.inputs.clear();
         let mut pyo3imports = Vec::<Pyo3Import>::new();
+        let mut pyo3codes = Vec::<Pyo3Code>::new();
+        let mut pyo3params: Option<Pyo3Params> = None;
         let mut otherattributes = Vec::<Attribute>::new();
         for attr in testcase.attrs {
             if attr.path().is_ident("pyo3import") {
                 pyo3imports.push(attr.parse_args()?);
+            } else if attr.path().is_ident("pyo3code") {
+                pyo3codes.push(attr.parse_args()?);
+            } else if attr.path().is_ident("pyo3params") {
+                if pyo3params.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        attr,
+                        "only one #[pyo3params(...)] is allowed, list all the cases in it",
+                    ));
+                }
+                pyo3params = Some(attr.parse_args()?);
             } else {
                 otherattributes.push(attr);
             };
@@ -57,59 +124,646 @@ impl TryFrom<ItemFn> for Pyo3TestCase {
 
         Ok(Pyo3TestCase {
             pyo3imports,
+            pyo3codes,
+            pyo3params,
+            py,
+            fixtures,
             signature: testcase.sig,
             statements: testcase.block.stmts,
             otherattributes,
+            isolated: false,
         })
     }
 }
 
-/// A python `import` statement for a pyo3-wrapped function.
+/// An inline python module, which can then be imported like any other python module.
+#[derive(Debug, PartialEq)]
+struct Pyo3Code {
+    /// The name of the module
+    name: LitStr,
+    /// The python source code
+    code: LitStr,
+}
+
+impl Parse for Pyo3Code {
+    /// Attributes parsing to Pyo3Codes should have the format:
+    /// `name = "modulename", "python code"`, where the code will usually be a raw string: `r#"..."#`
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let example = "\nE.g.: `#[pyo3code(name = \"helpers\", r#\"def two(): return 2\"#)]`";
+        let keyword: Ident = input.parse()?;
+        if keyword != "name" {
+            return Err(syn::Error::new(
+                keyword.span(),
+                "invalid python code: expected `name = \"modulename\"` here".to_string() + example,
+            ));
+        }
+        let _equals: Token![=] = input.parse()?;
+        let name: LitStr = input.parse()?;
+        let _comma: Comma = input.parse().map_err(|error| {
+            syn::Error::new(
+                error.span(),
+                "invalid python code: expected `,` and then the code after the name".to_string()
+                    + example,
+            )
+        })?;
+        let code: LitStr = match input.parse() {
+            Ok(code) => code,
+            Err(error) => {
+                return Err(syn::Error::new(
+                    error.span(),
+                    "invalid python code: expected a string containing the code here".to_string()
+                        + example,
+                ))
+            }
+        };
+        let _trailingcomma: Option<Comma> = input.parse()?;
+        Ok(Pyo3Code { name, code })
+    }
+}
+
+/// Remove any common leading whitespace from every line of `code`, so that python code can be
+/// indented to match the surrounding rust code, like python's `textwrap.dedent`.
+fn dedent(code: &str) -> String {
+    let indent = code
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or_default();
+    code.lines()
+        .map(|line| line.get(indent..).unwrap_or_default())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+syn::custom_keyword!(err);
+
+/// The parameters of a parametrised testcase, like `@pytest.mark.parametrize`. Each case is
+/// expanded into a separate test, with the values bound to the names of the parameters.
+#[derive(Debug, PartialEq)]
+struct Pyo3Params {
+    names: Vec<Ident>,
+    cases: Vec<Pyo3Case>,
+}
+
+/// A single case of a parametrised testcase
+#[derive(Debug, PartialEq)]
+struct Pyo3Case {
+    /// Attributes for just this case, e.g. `#[should_panic]`, given before it
+    attrs: Vec<Attribute>,
+    /// The name of the test for this case, if given as `name: (value, value)`
+    id: Option<Ident>,
+    /// One value for each parameter
+    values: Vec<ParamValue>,
+}
+
+/// The value of a single parameter in one case
+#[derive(Debug, PartialEq)]
+enum ParamValue {
+    /// Any rust expression, bound with `let name = value;`
+    Value(Expr),
+    /// `err ExceptionType`, the case expects the testcase to raise this exception
+    Err(Path),
+}
+
+impl Parse for ParamValue {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        if input.peek(err) {
+            let _err: err = input.parse()?;
+            Ok(ParamValue::Err(input.parse()?))
+        } else {
+            Ok(ParamValue::Value(input.parse()?))
+        }
+    }
+}
+
+impl Parse for Pyo3Params {
+    /// Attributes parsing to Pyo3Params should have the format:
+    /// `name, name; (value, value), (value, err ExceptionType), ...`. With only one parameter there
+    /// are no brackets around each case, so that tuples can be used as values: `name; value, value`.
+    /// Any case can be given a name: `name, name; id: (value, value), ...` and attributes of its own:
+    /// `name, name; #[should_panic] (value, value), ...`
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let example =
+            "\nE.g.: `#[pyo3params(input, expected; (1, 2), (5, 6), (\"x\", err PyTypeError))]`";
+        let names: Vec<Ident> = Punctuated::<Ident, Comma>::parse_separated_nonempty(input)
+            .map_err(|e| {
+                syn::Error::new(
+                    e.span(),
+                    "expected the names of the parameters".to_string() + example,
+                )
+            })?
+            .into_iter()
+            .collect();
+        input.parse::<Token![;]>().map_err(|e| {
+            syn::Error::new(
+                e.span(),
+                "expected `;` and then the cases after the names of the parameters".to_string()
+                    + example,
+            )
+        })?;
+        let mut cases = Vec::<Pyo3Case>::new();
+        while !input.is_empty() {
+            let attrs = input.call(Attribute::parse_outer)?;
+            let mut id = None;
+            if input.peek(Ident) && input.peek2(Token![:]) && !input.peek2(Token![::]) {
+                id = Some(input.parse()?);
+                input.parse::<Token![:]>()?;
+            }
+            if names.len() == 1 {
+                cases.push(Pyo3Case {
+                    attrs,
+                    id,
+                    values: vec![input.parse()?],
+                });
+            } else {
+                let values;
+                let brackets = parenthesized!(values in input);
+                let values: Vec<ParamValue> =
+                    Punctuated::<ParamValue, Comma>::parse_terminated(&values)?
+                        .into_iter()
+                        .collect();
+                if values.len() != names.len() {
+                    let names: Vec<String> = names.iter().map(Ident::to_string).collect();
+                    return Err(syn::Error::new(
+                        brackets.span.join(),
+                        format!(
+                            "expected {} values, one for each of: {}",
+                            names.len(),
+                            names.join(", ")
+                        ) + example,
+                    ));
+                }
+                cases.push(Pyo3Case { attrs, id, values });
+            }
+            if !input.is_empty() {
+                input.parse::<Comma>()?;
+            }
+        }
+        if cases.is_empty() {
+            return Err(input.error("expected at least one case".to_string() + example));
+        }
+        Ok(Pyo3Params { names, cases })
+    }
+}
+
+impl Pyo3Params {
+    /// Whether the parameter is `err` in every case, so can be bound to the exception type
+    fn all_err(&self, column: usize) -> bool {
+        self.cases
+            .iter()
+            .all(|case| matches!(case.values[column], ParamValue::Err(_)))
+    }
+
+    /// The name of the test for each case: either the id given, or built from the values, like
+    /// pytest does, e.g. `case_1_2` for `(1, 2)` or `case_x_pytypeerror` for `("x", err PyTypeError)`.
+    /// Values other than literals and names are replaced by the name of the parameter and the number
+    /// of the case, e.g. `case_input3_4`.
+    fn case_names(&self) -> Vec<Ident> {
+        let mut used: Vec<String> = self
+            .cases
+            .iter()
+            .filter_map(|case| case.id.as_ref().map(Ident::to_string))
+            .collect();
+        self.cases
+            .iter()
+            .enumerate()
+            .map(|(i, case)| {
+                if let Some(id) = &case.id {
+                    return id.clone();
+                }
+                let number = i + 1;
+                let parts: Vec<String> = self
+                    .names
+                    .iter()
+                    .zip(&case.values)
+                    .map(|(name, value)| {
+                        let part = match value {
+                            ParamValue::Value(Expr::Lit(literal)) => match &literal.lit {
+                                Lit::Str(string) => string.value(),
+                                Lit::Int(int) => int.base10_digits().to_string(),
+                                Lit::Float(float) => float.base10_digits().to_string(),
+                                Lit::Bool(bool) => bool.value.to_string(),
+                                Lit::Char(char) => char.value().to_string(),
+                                _ => String::new(),
+                            },
+                            ParamValue::Value(Expr::Path(path)) => path
+                                .path
+                                .get_ident()
+                                .map(Ident::to_string)
+                                .unwrap_or_default(),
+                            ParamValue::Err(exception) => exception
+                                .segments
+                                .last()
+                                .map(|segment| segment.ident.to_string())
+                                .unwrap_or_default(),
+                            ParamValue::Value(_) => String::new(),
+                        };
+                        let part = part
+                            .to_lowercase()
+                            .split(|c: char| !c.is_ascii_alphanumeric())
+                            .filter(|word| !word.is_empty())
+                            .collect::<Vec<&str>>()
+                            .join("_");
+                        if part.is_empty() {
+                            format!("{name}{number}")
+                        } else {
+                            part
+                        }
+                    })
+                    .collect();
+                let mut casename = format!("case_{}", parts.join("_"));
+                if used.contains(&casename) {
+                    casename = format!("{casename}_{number}");
+                }
+                used.push(casename.clone());
+                format_ident!("{}", casename)
+            })
+            .collect()
+    }
+
+    /// The name and statements for each case: the parameters are bound just before the statements
+    /// of the testcase.
+    ///
+    /// A parameter which is `err` in every case is bound to the exception type, for use with
+    /// `with_py_raises!`. Otherwise a case with an `err` value expects the statements to raise that
+    /// exception: the call macros are routed to their `try_` equivalents and the first error is
+    /// returned and checked, just like `with_py_raises!`. Only the statements up to the last one
+    /// which can raise (a call macro or `?`) are run, so the check happens before any assertions and
+    /// the parameter never needs a value.
+    fn expand_cases(
+        &self,
+        signature: &Signature,
+        statements: &[Stmt],
+        router: &CallMacroRouter,
+    ) -> syn::Result<Vec<(Ident, TokenStream2)>> {
+        let result = Ident::new("result", Span::mixed_site());
+        self.case_names()
+            .into_iter()
+            .zip(&self.cases)
+            .map(|(casename, case)| {
+                let mut raises = Vec::<&Path>::new();
+                let mut bindings = Vec::<TokenStream2>::new();
+                let mut unbound = Vec::<(&Ident, &Path)>::new();
+                for (column, (name, value)) in self.names.iter().zip(&case.values).enumerate() {
+                    match value {
+                        ParamValue::Value(value) => bindings.push(quote! { let #name = #value; }),
+                        ParamValue::Err(exception) if self.all_err(column) => bindings.push(quote! {
+                            #[allow(non_camel_case_types)]
+                            type #name = #exception;
+                        }),
+                        ParamValue::Err(exception) => {
+                            raises.push(exception);
+                            unbound.push((name, exception));
+                        }
+                    }
+                }
+                if raises.is_empty() {
+                    return Ok((casename, quote! { #(#bindings)* #(#statements)* }));
+                }
+                let passed = match &signature.output {
+                    ReturnType::Default => quote! {},
+                    ReturnType::Type(_, returntype) if is_pyresult(returntype) => {
+                        quote! { ::std::result::Result::Ok(::std::default::Default::default()) }
+                    }
+                    ReturnType::Type(..) => {
+                        return Err(syn::Error::new_spanned(
+                            &signature.output,
+                            "a testcase with `err` cases must return nothing or a `PyResult`",
+                        ))
+                    }
+                };
+                let mut statements = statements.to_vec();
+                let mut last = None;
+                for (index, statement) in statements.iter_mut().enumerate() {
+                    let mut router = TryRouter {
+                        router,
+                        raises: false,
+                    };
+                    router.visit_stmt_mut(statement);
+                    if router.raises {
+                        last = Some(index);
+                    }
+                }
+                let Some(last) = last else {
+                    return Err(syn::Error::new_spanned(
+                        raises[0],
+                        "an `err` case expects the testcase to raise, but it has no call macros or `?`",
+                    ));
+                };
+                // items may be used before they are declared, so are always kept
+                let mut statements: Vec<Stmt> = statements
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(index, statement)| *index <= last || matches!(statement, Stmt::Item(_)))
+                    .map(|(_, statement)| statement)
+                    .collect();
+                if let Some(Stmt::Expr(tail, None)) = statements.get(last) {
+                    statements[last] = parse_quote! { let _ = #tail; };
+                }
+                let kept = quote! { #(#statements)* };
+                for (name, exception) in unbound {
+                    if uses_ident(kept.clone(), name) {
+                        return Err(syn::Error::new_spanned(
+                            exception,
+                            format!(
+                                "`{name}` is `err` in this case, so can only be used after the last call macro or `?`, which should raise it"
+                            ),
+                        ));
+                    }
+                }
+                let expected = raises
+                    .iter()
+                    .map(|exception| exception.to_token_stream().to_string().replace(' ', ""))
+                    .collect::<Vec<String>>()
+                    .join(" or ");
+                let raises = match raises.as_slice() {
+                    [exception] => quote! { #exception },
+                    exceptions => quote! { (#(#exceptions),*) },
+                };
+                let check = impl_with_py_raises(quote! { #raises, { #result } });
+                let expanded = quote! {
+                    #(
+                        #[allow(unused_variables)]
+                        #bindings
+                    )*
+                    // the statements after the last which can raise are not run
+                    #[allow(unused_variables)]
+                    let #result = (|| -> ::pyo3::PyResult<()> {
+                        #kept
+                        ::std::result::Result::Ok(())
+                    })();
+                    if #result.is_ok() {
+                        panic!("No Error: expected {} but the testcase did not raise it", #expected);
+                    }
+                    let _ = #check;
+                    #passed
+                };
+                Ok((casename, expanded))
+            })
+            .collect()
+    }
+}
+
+/// Whether the ident is used anywhere in the tokens, including inside macros
+fn uses_ident(tokens: TokenStream2, ident: &Ident) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(other) => other == *ident,
+        TokenTree::Group(group) => uses_ident(group.stream(), ident),
+        _ => false,
+    })
+}
+
+/// A python `import` statement for a pyo3-wrapped function, or from a python module.
 #[derive(Debug, PartialEq)]
 struct Pyo3Import {
-    /// The *rust* `ident` of the wrapped module
-    o3_moduleident: Ident,
-    /// The *python* module name
-    py_modulename: String,
-    /// The *python* function name
-    py_functionname: Option<String>,
+    /// Where the module comes from
+    source: ModuleSource,
+    /// The *python* module, which may be a dotted path to a submodule, e.g. `package.module`
+    py_module: PythonName,
+    /// The *python* functions, empty for `import module` and `from module import *`
+    py_functions: Vec<PythonName>,
+    /// The *python* classes, imported with `from module import class ...`
+    py_classes: Vec<PythonClass>,
+    /// The rust `ident` of the namespace to populate at runtime for `from module import *`
+    py_namespace: Option<Ident>,
 }
 
 impl Parse for Pyo3Import {
     /// Attributes parsing to Pyo3Imports should have the format:
-    /// `moduleidentifier: from modulename import functionname`
+    /// `moduleidentifier: from modulename import functionname, ...` (each optionally `as alias`,
+    /// optionally wrapped in brackets, classes marked as `class classname`) OR
+    /// `moduleidentifier: from modulename import *` OR
+    /// `moduleidentifier: import modulename` (optionally `as alias`)
+    ///
+    /// `modulename` may be a dotted path to a submodule, e.g. `package.module`, in which case
+    /// `moduleidentifier` is the rust `ident` of the top-level package.
+    ///
+    /// For pure python modules (e.g. from the standard library or site-packages) leave out the
+    /// `moduleidentifier:`, e.g. `from decimal import Decimal`.
+    ///
+    /// For python source files use `file "path/to/file.py":` in place of `moduleidentifier:`, where
+    /// the path is relative to the crate root.
     fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
         // Written by a rust newbie, if there is a better option than all these assignments; please
         // feel free to change this code...
-        let o3_moduleident;
-        if input.peek2(Token![:]) {
-            o3_moduleident = input.parse()?;
+        let source;
+        if input.peek(file) && input.peek2(LitStr) {
+            let _file: file = input.parse()?;
+            source = ModuleSource::File(input.parse()?);
+            let _: Colon = input.parse().map_err(|_| {
+                input.error("invalid import statement: expected a colon (':') after the file path")
+            })?;
+        } else if input.peek2(Token![:]) {
+            source = ModuleSource::Rust(input.parse()?);
             let _: Colon = input.parse()?;
+        } else if input.fork().parse::<PythonImportKeyword>().is_ok() {
+            source = ModuleSource::Python;
         } else {
             return Err(input.error("invalid import statement: expected a colon (':') after this"));
         }
         let firstkeyword: PythonImportKeyword = input.parse()?;
-        let py_modulename = input.parse::<Ident>()?.to_string();
-        let py_functionname = match firstkeyword {
+        let mut py_namespace = None;
+        let (py_module, py_names) = match firstkeyword {
             PythonImportKeyword::from => {
+                let py_module = PythonName::parse_module(input, false)?;
                 let _import: PythonImportKeyword = input.parse()?;
-                Some(input.parse::<Ident>()?.to_string())
+                let py_names = if input.peek(Token![*]) {
+                    let _star: Token![*] = input.parse()?;
+                    py_namespace = Some(format_ident!("{}_ns", py_module.ident));
+                    Punctuated::new()
+                } else if input.peek(Paren) {
+                    let names;
+                    let brackets = parenthesized!(names in input);
+                    let py_names = Punctuated::<ImportedName, Comma>::parse_terminated(&names)?;
+                    if py_names.is_empty() {
+                        return Err(syn::Error::new(
+                            brackets.span.join(),
+                            "invalid import statement: expected at least one name to import",
+                        ));
+                    }
+                    py_names
+                } else {
+                    Punctuated::<ImportedName, Comma>::parse_separated_nonempty(input)?
+                };
+                (py_module, py_names.into_iter().collect())
             }
-            PythonImportKeyword::import => None,
+            PythonImportKeyword::import => (PythonName::parse_module(input, true)?, Vec::new()),
         };
+        let mut py_functions = Vec::<PythonName>::new();
+        let mut py_classes = Vec::<PythonClass>::new();
+        for py_name in py_names {
+            match py_name {
+                ImportedName::Function(py_function) => py_functions.push(py_function),
+                ImportedName::Class(py_class) => py_classes.push(py_class),
+            }
+        }
 
         Ok(Pyo3Import {
-            o3_moduleident,
-            py_modulename,
-            py_functionname,
+            source,
+            py_module,
+            py_functions,
+            py_classes,
+            py_namespace,
         })
     }
 }
 
-/// Only the keywords `from` and `import` are valid for a python import statement, which has to take
-/// the form: `from x import y` or `import x`.
-/// Note we do not accept the additional keyword `as` by design: this is a simple testing framework
-/// to validate correct binding, type conversion and errorhandling.
+/// Where the module being imported comes from
+#[derive(Debug, PartialEq)]
+enum ModuleSource {
+    /// A `#[pymodule]`, given by the *rust* `ident` of the wrapped module
+    Rust(Ident),
+    /// A python source file, given by its path relative to the crate root
+    File(LitStr),
+    /// A pure python module, which is imported as normal
+    Python,
+}
+
+syn::custom_keyword!(class);
+syn::custom_keyword!(file);
+
+/// A single name in `from module import ...`, which is a function unless marked as a `class`
+enum ImportedName {
+    Function(PythonName),
+    Class(PythonClass),
+}
+
+impl Parse for ImportedName {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        if input.peek(class) {
+            Ok(ImportedName::Class(input.parse()?))
+        } else {
+            Ok(ImportedName::Function(input.parse()?))
+        }
+    }
+}
+
+/// A `#[pyclass]` being imported, together with the rust type which implements it.
+#[derive(Debug, PartialEq)]
+struct PythonClass {
+    /// The python name of the class and the rust ident to name its constructor macros
+    py_name: PythonName,
+    /// The rust type of the `#[pyclass]`
+    o3_type: Path,
+}
+
+impl Parse for PythonClass {
+    /// Classes are imported as `class ClassName`, optionally followed by `as alias`. The rust type
+    /// is assumed to have the same name as the python class, unless given using a type annotation:
+    /// `class ClassName: RustType`
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let _class: class = input.parse()?;
+        let py_name: PythonName = input.parse()?;
+        let o3_type = if input.peek(Token![:]) {
+            let _: Colon = input.parse()?;
+            input.parse()?
+        } else {
+            Ident::new(&py_name.name, py_name.ident.span()).into()
+        };
+        Ok(PythonClass { py_name, o3_type })
+    }
+}
+
+/// A python name being imported and the rust ident it will be bound to, which is the same as the
+/// python name unless renamed with `as`. E.g. `addone` or `addone as add1`.
+#[derive(Debug, PartialEq)]
+struct PythonName {
+    /// The name in python
+    name: String,
+    /// The rust ident to bind the imported object to
+    ident: Ident,
+}
+
+impl PythonName {
+    /// Parse a, possibly dotted, module name, e.g. `adders` or `package.module`. The module is bound
+    /// to the last part of the name, unless `alias` is allowed and it is renamed with `as`.
+    fn parse_module(input: ParseStream<'_>, alias: bool) -> syn::Result<Self> {
+        let mut leaf = parse_python_identifier(input)?;
+        let mut name = leaf.to_string();
+        while input.peek(Token![.]) {
+            let _dot: Token![.] = input.parse()?;
+            leaf = parse_python_identifier(input)?;
+            name = name + "." + &leaf.to_string();
+        }
+        if alias {
+            if let Some(ident) = parse_alias(input)? {
+                return Ok(PythonName::new(name, ident));
+            }
+        }
+        if is_rust_keyword(&leaf) {
+            let message = if alias {
+                format!("invalid import statement: `{leaf}` is a rust keyword, use `{name} as alias` to bind it to a different name")
+            } else {
+                format!(
+                    "invalid import statement: `{leaf}` is a rust keyword and cannot be used here"
+                )
+            };
+            return Err(syn::Error::new(leaf.span(), message));
+        }
+        Ok(PythonName::new(name, leaf))
+    }
+
+    /// The binding is created with `call_site` hygiene, in the same way as all the other idents we
+    /// generate, rather than the span of the attribute it was parsed from.
+    fn new(name: String, mut ident: Ident) -> Self {
+        ident.set_span(Span::call_site());
+        PythonName { name, ident }
+    }
+}
+
+impl Parse for PythonName {
+    /// Python names may be rust keywords (e.g. `type`), as long as they are renamed with `as`.
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let name = parse_python_identifier(input)?;
+        if let Some(ident) = parse_alias(input)? {
+            return Ok(PythonName::new(name.to_string(), ident));
+        }
+        if is_rust_keyword(&name) {
+            return Err(syn::Error::new(
+                name.span(),
+                format!("invalid import statement: `{name}` is a rust keyword, use `{name} as alias` to bind it to a different name"),
+            ));
+        }
+        Ok(PythonName::new(name.to_string(), name))
+    }
+}
+
+/// Parse an optional `as alias`.
+fn parse_alias(input: ParseStream<'_>) -> syn::Result<Option<Ident>> {
+    if input.peek(Token![as]) {
+        let _as: Token![as] = input.parse()?;
+        return Ok(Some(input.parse()?));
+    }
+    Ok(None)
+}
+
+/// Whether a python identifier is a rust keyword, and so cannot be used as a binding.
+fn is_rust_keyword(ident: &Ident) -> bool {
+    parse2::<Ident>(ident.to_token_stream()).is_err()
+}
+
+/// Python identifiers may also be rust keywords, so we need to accept any identifier here, other
+/// than python's own keywords.
+fn parse_python_identifier(input: ParseStream<'_>) -> syn::Result<Ident> {
+    let ident = Ident::parse_any(input).map_err(|_| input.error("expected identifier"))?;
+    if PYTHON_KEYWORDS.contains(&ident.to_string().as_str()) {
+        return Err(syn::Error::new(ident.span(), "expected identifier"));
+    }
+    Ok(ident)
+}
+
+/// The python keywords, which cannot be used as names
+const PYTHON_KEYWORDS: [&str; 35] = [
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+
+/// Only the keywords `from` and `import` are valid at the start of a python import statement, which
+/// has to take the form: `from x import y` or `import x`. The additional keyword `as` is handled
+/// by `PythonName`.
 #[allow(non_camel_case_types)] // represent actual keywords in python which are lower case
 #[derive(Debug, PartialEq)]
 enum PythonImportKeyword {
@@ -141,7 +795,7 @@ impl Parse for PythonImportKeyword {
 /// ```
 /// and not `from module import function`
 #[allow(non_snake_case)] // follow python exception naming for error messages
-fn wrap_testcase(mut testcase: Pyo3TestCase) -> TokenStream2 {
+fn wrap_testcase(testcase: Pyo3TestCase) -> TokenStream2 {
     //The quote crate cannot interpolate fields within structs so we need to separate out all
     //import statements into Vecs of the individual fields. To make the final `quote` more readable,
     //we also construct the longer strings and the Idents in advance.
@@ -152,111 +806,706 @@ fn wrap_testcase(mut testcase: Pyo3TestCase) -> TokenStream2 {
     let mut o3_pymoduledefidents = Vec::<Ident>::new(); // interim idents representing the moduledef for PEP 489 multi-phase initialization
     let mut o3_pymoduleidents = Vec::<Ident>::new(); // interim idents representing the modules after initial binding to the GIL token
     let mut py_moduleidents = Vec::<Ident>::new(); // final idents representing the imported modules
-    let mut py_modulenames = Vec::<String>::new(); // The module names
+    let mut py_modulenames = Vec::<String>::new(); // The (possibly dotted) module names
+    let mut py_packagenames = Vec::<String>::new(); // The top-level module names, which are created from rust
     let mut py_ModuleNotFoundErrormsgs = Vec::<String>::new(); // The error messages to give if the module is invalid
+    let mut py_parentnames = Vec::<Vec<String>>::new(); // For each import: the dotted names of the parents of any submodules
+    let mut py_submodulenames = Vec::<Vec<String>>::new(); // For each import: the names of any submodules within their parent
+    let mut py_submodulepaths = Vec::<Vec<String>>::new(); // For each import: the dotted names of any submodules
+    let mut py_SubmoduleNotFoundErrormsgs = Vec::<Vec<String>>::new(); // The error messages to give if a submodule is invalid
     let mut py_functionidents = Vec::<Ident>::new(); // idents representing the imported functions
+    let mut py_filemoduleidents = Vec::<Ident>::new(); // final idents representing the modules imported from python source files
+    let mut py_filemodulenames = Vec::<String>::new(); // The names of the modules imported from python source files
+    let mut py_filepaths = Vec::<LitStr>::new(); // The paths of the python source files, relative to the crate root
+    let mut py_fileModuleNotFoundErrormsgs = Vec::<String>::new(); // The error messages to give if the python source file is invalid
+    let mut py_codemodulenames = Vec::<String>::new(); // The names of the inline python modules
+    let mut py_codes = Vec::<String>::new(); // The (dedented) source code of the inline python modules
+    let mut py_codefilenames = Vec::<String>::new(); // The filenames to show in tracebacks from inline python modules
+    let mut py_codeErrormsgs = Vec::<String>::new(); // The error messages to give if the inline python is invalid
+    let mut py_pythonmoduleidents = Vec::<Ident>::new(); // final idents representing the imported pure python modules
+    let mut py_pythonmodulenames = Vec::<String>::new(); // The pure python module names
+    let mut py_pythonModuleNotFoundErrormsgs = Vec::<String>::new(); // The error messages to give if the pure python module is invalid
     let mut py_macroidents = Vec::<Ident>::new(); // idents representing the macro_rules! used to call the functions
+    let mut py_trymacroidents = Vec::<Ident>::new(); // idents representing the fallible macro_rules! used to call the functions
     let mut py_moduleswithfnsidents = Vec::<Ident>::new(); // final idents representing the imported modules (only those with named function imports)
     let mut py_functionnames = Vec::<String>::new(); // The function names
     let mut py_AttributeErrormsgs = Vec::<String>::new(); // The error messages to give if the function is invalid
+    let mut py_classidents = Vec::<Ident>::new(); // idents representing the macro_rules! used to construct the classes
+    let mut py_classobjectidents = Vec::<Ident>::new(); // hygienic idents representing the imported classes, which cannot clash with their rust types
+    let mut py_tryclassidents = Vec::<Ident>::new(); // idents representing the fallible macro_rules! used to construct the classes
+    let mut o3_classtypes = Vec::<Path>::new(); // The rust types of the classes
+    let mut py_moduleswithclassesidents = Vec::<Ident>::new(); // final idents representing the imported modules (only those with class imports)
+    let mut py_classnames = Vec::<String>::new(); // The class names
+    let mut py_classAttributeErrormsgs = Vec::<String>::new(); // The error messages to give if the class is invalid
+    let mut py_namespaceidents = Vec::<Ident>::new(); // idents representing the namespaces for `import *`, also used for their macro_rules!
+    let mut py_trynamespaceidents = Vec::<Ident>::new(); // idents representing the fallible macro_rules! for each namespace
+    let mut py_moduleswithnamespacesidents = Vec::<Ident>::new(); // final idents representing the imported modules (only those with `import *`)
+    let mut py_namespaceAttributeErrormsgs = Vec::<String>::new(); // The start of the error messages to give if a function is not in the namespace
+
+    for pyo3code in testcase.pyo3codes {
+        let py_codemodulename = pyo3code.name.value();
+        py_codeErrormsgs
+            .push("Failed to execute python code for ".to_string() + &py_codemodulename);
+        py_codefilenames.push(format!("<pyo3code {py_codemodulename}>"));
+        py_codes.push(dedent(&pyo3code.code.value()));
+        py_codemodulenames.push(py_codemodulename);
+    }
 
     for pyo3import in testcase.pyo3imports {
         // statements ordered to allow multiple borrows of module and functionname before moving to Vec
-        let py_modulename = pyo3import.py_modulename;
-        if let Some(py_functionname) = pyo3import.py_functionname {
+        let py_modulename = pyo3import.py_module.name;
+        let py_moduleident = pyo3import.py_module.ident;
+        for py_function in pyo3import.py_functions {
+            let py_functionname = py_function.name;
             py_AttributeErrormsgs
                 .push("Failed to get ".to_string() + &py_functionname + " function");
-            py_functionidents.push(Ident::new(&py_functionname, Span::call_site()));
-            py_macroidents.push(Ident::new(&py_functionname, Span::call_site()));
-            py_moduleswithfnsidents.push(Ident::new(&py_modulename, Span::call_site()));
+            py_trymacroidents.push(format_ident!("try_{}", py_function.ident));
+            py_macroidents.push(py_function.ident.clone());
+            py_functionidents.push(py_function.ident);
+            py_moduleswithfnsidents.push(py_moduleident.clone());
             py_functionnames.push(py_functionname);
+        }
+        for py_class in pyo3import.py_classes {
+            let py_classname = py_class.py_name.name;
+            py_classAttributeErrormsgs
+                .push("Failed to get ".to_string() + &py_classname + " class");
+            py_tryclassidents.push(format_ident!("try_{}", py_class.py_name.ident));
+            py_classobjectidents.push(Ident::new(
+                &format!("{}_class", py_class.py_name.ident),
+                Span::mixed_site(),
+            ));
+            py_classidents.push(py_class.py_name.ident);
+            o3_classtypes.push(py_class.o3_type);
+            py_moduleswithclassesidents.push(py_moduleident.clone());
+            py_classnames.push(py_classname);
+        }
+        if let Some(py_namespace) = pyo3import.py_namespace {
+            py_namespaceAttributeErrormsgs.push(format!("{py_modulename} does not export "));
+            py_trynamespaceidents.push(format_ident!("try_{}", py_namespace));
+            py_namespaceidents.push(py_namespace);
+            py_moduleswithnamespacesidents.push(py_moduleident.clone());
+        }
+        let o3_moduleident = match pyo3import.source {
+            ModuleSource::Rust(o3_moduleident) => o3_moduleident,
+            ModuleSource::File(py_filepath) => {
+                py_fileModuleNotFoundErrormsgs
+                    .push("Failed to import ".to_string() + &py_filepath.value());
+                py_filepaths.push(py_filepath);
+                py_filemoduleidents.push(py_moduleident);
+                py_filemodulenames.push(py_modulename);
+                continue;
+            }
+            ModuleSource::Python => {
+                // pure python modules are simply imported, python takes care of any packages
+                py_pythonModuleNotFoundErrormsgs
+                    .push("Failed to import ".to_string() + &py_modulename);
+                py_pythonmoduleidents.push(py_moduleident);
+                py_pythonmodulenames.push(py_modulename);
+                continue;
+            }
         };
-        py_ModuleNotFoundErrormsgs.push("Failed to import ".to_string() + &py_modulename);
-        py_moduleidents.push(Ident::new(&py_modulename, Span::call_site()));
+        // `package.module.submodule` is registered as `package`, `package.module` and
+        // `package.module.submodule`, each submodule being an attribute of its parent
+        let mut py_modulepath = py_modulename.split('.');
+        let py_packagename = py_modulepath.next().unwrap_or_default().to_string();
+        let mut py_parentname = py_packagename.clone();
+        let mut parentnames = Vec::<String>::new();
+        let mut submodulenames = Vec::<String>::new();
+        let mut submodulepaths = Vec::<String>::new();
+        for py_submodulename in py_modulepath {
+            let py_submodulepath = py_parentname.clone() + "." + py_submodulename;
+            parentnames.push(py_parentname);
+            submodulenames.push(py_submodulename.to_string());
+            submodulepaths.push(py_submodulepath.clone());
+            py_parentname = py_submodulepath;
+        }
+        py_SubmoduleNotFoundErrormsgs.push(
+            submodulepaths
+                .iter()
+                .map(|path| "Failed to import ".to_string() + path)
+                .collect(),
+        );
+        py_parentnames.push(parentnames);
+        py_submodulenames.push(submodulenames);
+        py_submodulepaths.push(submodulepaths);
+        py_ModuleNotFoundErrormsgs.push("Failed to import ".to_string() + &py_packagename);
+        py_packagenames.push(py_packagename);
+        py_moduleidents.push(py_moduleident);
         py_modulenames.push(py_modulename);
-        o3_pymoduledefidents.push(format_ident!("{}_pymoduledef", pyo3import.o3_moduleident));
-        o3_pymoduleidents.push(format_ident!("{}_pymodule", pyo3import.o3_moduleident));
-        o3_moduleidents.push(pyo3import.o3_moduleident);
+        o3_pymoduledefidents.push(format_ident!("{}_pymoduledef", o3_moduleident));
+        o3_pymoduleidents.push(format_ident!("{}_pymodule", o3_moduleident));
+        o3_moduleidents.push(o3_moduleident);
     }
 
+    let py = testcase.py;
+    let sys_modules_guard = Ident::new("sys_modules_guard", Span::mixed_site());
+    // sys.modules is only needed if we have modules to register
+    let sys_modules = if o3_moduleidents.is_empty()
+        && py_filemoduleidents.is_empty()
+        && py_codemodulenames.is_empty()
+    {
+        quote! {}
+    } else {
+        quote! {
+            // from sys import modules as sys_modules
+            let sys = PyModule::import(#py, "sys").unwrap();
+            let sys_modules: Bound<'_, PyDict> =
+                sys.getattr("modules").unwrap().cast_into().unwrap();
+
+            // register modules via the guard, which puts back whatever was there before when the
+            // testcase finishes, even if it panics, so that our modules do not leak into other tests.
+            // Any other changes to sys.modules, and keys which another test has since replaced, are
+            // left alone, as the interpreter is shared by all the tests running in parallel
+            struct SysModulesGuard<'py> {
+                sys_modules: Bound<'py, PyDict>,
+                // each key we set, with the module we set and the value it replaced
+                registered: Vec<(String, Bound<'py, PyAny>, Option<Bound<'py, PyAny>>)>,
+            }
+            impl<'py> SysModulesGuard<'py> {
+                // `create` may itself insert the module, as `PyModule::from_code` does, so we need
+                // to get whatever was there before calling it
+                fn register(
+                    &mut self,
+                    key: &str,
+                    create: impl FnOnce() -> PyResult<Bound<'py, PyAny>>,
+                ) -> PyResult<Bound<'py, PyAny>> {
+                    let previous = self.sys_modules.get_item(key)?;
+                    let module = create()?;
+                    self.sys_modules.set_item(key, &module)?;
+                    self.registered.push((key.to_string(), module.clone(), previous));
+                    Ok(module)
+                }
+            }
+            impl Drop for SysModulesGuard<'_> {
+                fn drop(&mut self) {
+                    // never panic here, we may already be unwinding
+                    for (key, module, previous) in self.registered.drain(..).rev() {
+                        let current = self.sys_modules.get_item(&key).ok().flatten();
+                        if current.is_some_and(|current| current.is(&module)) {
+                            let _ = match previous {
+                                Some(previous) => self.sys_modules.set_item(&key, previous),
+                                None => self.sys_modules.del_item(&key),
+                            };
+                        }
+                    }
+                }
+            }
+            let mut #sys_modules_guard = SysModulesGuard {
+                sys_modules: sys_modules.clone(),
+                registered: Vec::new(),
+            };
+        }
+    };
+
+    let fixtures = use_fixtures(&testcase.fixtures, &py);
     let testfn_signature = testcase.signature;
-    let testfn_statements = testcase.statements;
+    let mut testfn_statements = testcase.statements;
+    let call_macros = [py_macroidents.as_slice(), py_namespaceidents.as_slice()].concat();
+    if !call_macros.is_empty() || !py_classidents.is_empty() {
+        let mut router = CallMacroRouter {
+            call_macros: &call_macros,
+            class_macros: &py_classidents,
+        };
+        for statement in testfn_statements.iter_mut() {
+            router.visit_stmt_mut(statement);
+        }
+    }
+
+    // everything needed before the statements of the testcase, which is the same for each case of a
+    // parametrised testcase
+    let setup = quote! {
+        #sys_modules
+
+        #( // for each module to import
+
+            // get the python moduledef from the hidden rust module
+            let #o3_pymoduledefidents = &#o3_moduleidents::_PYO3_DEF;
+
+            // manually create unbound python module
+            // (temp rust binding to avoid dropping too early)
+            let #o3_pymoduleidents = #o3_pymoduledefidents
+                .make_module(#py)
+                .unwrap();
+            // and then bind module to py
+            let #o3_pymoduleidents = #o3_pymoduleidents.bind(#py);
+
+            // insert module into sys_modules
+            #sys_modules_guard
+                .register(#py_packagenames, || Ok(#o3_pymoduleidents.clone().into_any()))
+                .expect(#py_ModuleNotFoundErrormsgs);
+
+            #( // for each submodule in a dotted module name
+
+                // insert submodule into sys_modules under its dotted name, as a real
+                // package import would
+                #sys_modules_guard
+                    .register(#py_submodulepaths, || {
+                        sys_modules
+                            .get_item(#py_parentnames)
+                            .unwrap()
+                            .unwrap()
+                            .getattr(#py_submodulenames)
+                    })
+                    .expect(#py_SubmoduleNotFoundErrormsgs);
+            )*
+
+            // and get it back - cannot fail as we just put it there
+            let #py_moduleidents = sys_modules.get_item(#py_modulenames).unwrap().unwrap();
+        )*
+
+        #( // for each python source file to import
+
+            // read the file at compile time and execute it as a module with the given name,
+            // which is inserted into sys_modules, so that python code can also import it
+            let #py_filemoduleidents = #sys_modules_guard
+                .register(#py_filemodulenames, || {
+                    PyModule::from_code(
+                        #py,
+                        &::std::ffi::CString::new(include_str!(concat!(
+                            env!("CARGO_MANIFEST_DIR"),
+                            "/",
+                            #py_filepaths
+                        )))
+                        .unwrap(),
+                        &::std::ffi::CString::new(#py_filepaths).unwrap(),
+                        &::std::ffi::CString::new(#py_filemodulenames).unwrap(),
+                    )
+                    .map(Bound::into_any)
+                })
+                .expect(#py_fileModuleNotFoundErrormsgs);
+        )*
+
+        #( // for each inline python module
+
+            // execute the code as a module and insert it into sys_modules, so that it can
+            // be imported
+            #sys_modules_guard
+                .register(#py_codemodulenames, || {
+                    PyModule::from_code(
+                        #py,
+                        &::std::ffi::CString::new(#py_codes).unwrap(),
+                        &::std::ffi::CString::new(#py_codefilenames).unwrap(),
+                        &::std::ffi::CString::new(#py_codemodulenames).unwrap(),
+                    )
+                    .map(Bound::into_any)
+                })
+                .expect(#py_codeErrormsgs);
+        )*
+
+        #( // for each pure python module to import
+            let #py_pythonmoduleidents = PyModule::import(#py, #py_pythonmodulenames)
+                .expect(#py_pythonModuleNotFoundErrormsgs)
+                .into_any();
+        )*
+
+        #( // for each function to import
+
+            // assign each wrapped function to a rust Ident of the same name
+            let #py_functionidents = #py_moduleswithfnsidents
+                .getattr(#py_functionnames)
+                .expect(#py_AttributeErrormsgs);
+
+            // create call macros last, so they have access to the py_functionidents we create
+            #[allow(unused_macros)]
+            macro_rules! #py_macroidents {
+                ($($arg:tt)*) => {
+                    ::pyo3_testing::pyo3call!(#py_functionidents($($arg)*))
+                };
+            };
+
+            // and the fallible equivalents
+            #[allow(unused_macros)]
+            macro_rules! #py_trymacroidents {
+                ($($arg:tt)*) => {
+                    ::pyo3_testing::pyo3trycall!(#py_functionidents($($arg)*))
+                };
+            };
+        )*
+
+        #( // for each class to import
+
+            // assign each wrapped class to a hidden rust Ident, so that the binding cannot shadow the
+            // rust type, e.g. a tuple struct
+            let #py_classobjectidents = #py_moduleswithclassesidents
+                .getattr(#py_classnames)
+                .expect(#py_classAttributeErrormsgs);
+
+            // create constructor macros, which return the new instance bound to the rust type
+            #[allow(unused_macros)]
+            macro_rules! #py_classidents {
+                ($($arg:tt)*) => {
+                    {
+                        let instance: ::pyo3::Bound<'_, #o3_classtypes> =
+                            ::pyo3_testing::pyo3call!(#py_classobjectidents($($arg)*));
+                        instance
+                    }
+                };
+            };
+
+            // and the fallible equivalents
+            #[allow(unused_macros)]
+            macro_rules! #py_tryclassidents {
+                ($($arg:tt)*) => {
+                    {
+                        let instance: ::pyo3::PyResult<::pyo3::Bound<'_, #o3_classtypes>> =
+                            ::pyo3_testing::pyo3trycall!(#py_classobjectidents($($arg)*));
+                        instance
+                    }
+                };
+            };
+        )*
 
+        #( // for each `from module import *`
+
+            // populate a namespace with everything the module exports: the names in
+            // `__all__`, if it is set, otherwise all public callables
+            let #py_namespaceidents = {
+                let names: Vec<String> = match #py_moduleswithnamespacesidents.getattr("__all__") {
+                    Ok(all) => all.extract().expect("__all__ should be a sequence of names"),
+                    Err(_) => #py_moduleswithnamespacesidents
+                        .dir()
+                        .unwrap()
+                        .iter()
+                        .map(|name| name.extract::<String>().unwrap())
+                        .filter(|name| {
+                            !name.starts_with('_')
+                                && #py_moduleswithnamespacesidents
+                                    .getattr(name.as_str())
+                                    .is_ok_and(|attr| attr.is_callable())
+                        })
+                        .collect(),
+                };
+                let namespace = PyModule::import(#py, "types")
+                    .unwrap()
+                    .getattr("SimpleNamespace")
+                    .unwrap()
+                    .call0()
+                    .unwrap();
+                for name in names {
+                    namespace
+                        .setattr(
+                            name.as_str(),
+                            #py_moduleswithnamespacesidents.getattr(name.as_str()).unwrap(),
+                        )
+                        .unwrap();
+                }
+                namespace
+            };
+
+            // create call macros which take the name of the function to call, as the
+            // names are only known at runtime: `namespace!(function(args))`
+            #[allow(unused_macros)]
+            macro_rules! #py_namespaceidents {
+                ($function:ident($($arg:tt)*)) => {
+                    {
+                        let $function = #py_namespaceidents
+                            .getattr(stringify!($function))
+                            .expect(concat!(#py_namespaceAttributeErrormsgs, stringify!($function)));
+                        ::pyo3_testing::pyo3call!($function($($arg)*))
+                    }
+                };
+            };
+
+            // and the fallible equivalents, which also return an `AttributeError` if the
+            // function is not in the namespace
+            #[allow(unused_macros)]
+            macro_rules! #py_trynamespaceidents {
+                ($function:ident($($arg:tt)*)) => {
+                    match #py_namespaceidents.getattr(stringify!($function)) {
+                        Ok($function) => ::pyo3_testing::pyo3trycall!($function($($arg)*)),
+                        Err(error) => Err(error),
+                    }
+                };
+            };
+        )*
+
+        #fixtures
+    };
+
+    let attributes = &testcase.otherattributes;
+    match testcase.pyo3params {
+        None => {
+            let statements = quote! { #(#testfn_statements)* };
+            build_testfn(
+                testfn_signature,
+                &py,
+                &setup,
+                statements,
+                attributes,
+                testcase.isolated,
+            )
+            .into_token_stream()
+        }
+        Some(pyo3params) => {
+            let testname = &testfn_signature.ident;
+            let router = CallMacroRouter {
+                call_macros: &call_macros,
+                class_macros: &py_classidents,
+            };
+            let cases =
+                match pyo3params.expand_cases(&testfn_signature, &testfn_statements, &router) {
+                    Ok(cases) => cases,
+                    Err(e) => return e.into_compile_error(),
+                };
+            let cases =
+                cases
+                    .into_iter()
+                    .zip(&pyo3params.cases)
+                    .map(|((name, statements), case)| {
+                        let mut signature = testfn_signature.clone();
+                        signature.ident = name;
+                        let attributes: Vec<Attribute> =
+                            attributes.iter().chain(&case.attrs).cloned().collect();
+                        let mut testfn = build_testfn(
+                            signature,
+                            &py,
+                            &setup,
+                            statements,
+                            &attributes,
+                            testcase.isolated,
+                        );
+                        // visible to the rest of the module, just like the original testcase
+                        testfn.vis = parse_quote!(pub(super));
+                        testfn
+                    });
+            quote! {
+                mod #testname {
+                    use super::*;
+
+                    #(#cases)*
+                }
+            }
+        }
+    }
+}
+
+/// Wraps the setup and statements of a testcase (or one case of a parametrised testcase) into a
+/// test, which runs them with the python interpreter available.
+fn build_testfn(
+    signature: Signature,
+    py: &Ident,
+    setup: &TokenStream2,
+    statements: TokenStream2,
+    attributes: &[Attribute],
+    isolated: bool,
+) -> ItemFn {
+    let (bind_result, returns, check_result) = check_result(&signature);
     let mut testfn: ItemFn = parse_quote!(
         #[test]
-        #testfn_signature {
+        #signature {
             use pyo3::types::PyDict;
             Python::initialize();
-            Python::attach(|py| {
+            #bind_result Python::attach(|#py| #returns {
+                #setup
 
-                // from sys import modules as sys_modules
-                let sys = PyModule::import(py, "sys").unwrap();
-                let sys_modules: Bound<'_, PyDict> =
-                    sys.getattr("modules").unwrap().cast_into().unwrap();
+                #statements
+            }) #check_result
+        }
+    );
+    testfn.attrs.extend(attributes.iter().cloned());
+    if isolated {
+        isolate(&mut testfn);
+    }
+    testfn
+}
 
-                #( // for each module to import
+/// Wraps the body of a testcase so that the test harness reruns *just this test* in a new process,
+/// giving it a fresh python interpreter, and reports the result.
+///
+/// Sub-interpreters are not an option as pyo3 refuses to initialise modules in them, and forking the
+/// multi-threaded test harness is unsafe. The child process is identified by an environment
+/// variable, which holds the name of the test to run.
+///
+/// The child must exit successfully *and* report that it ran exactly one test, otherwise a filter
+/// which matched nothing would pass without running the testcase at all.
+fn isolate(testfn: &mut ItemFn) {
+    let testname = testfn.sig.ident.to_string();
+    let testpath = Ident::new("testpath", Span::mixed_site());
+    let output = Ident::new("output", Span::mixed_site());
+    let stdout = Ident::new("stdout", Span::mixed_site());
+    // the harness only accepts `()` or a `Result` from a test, so any return value is `Ok`
+    let passed = match testfn.sig.output {
+        ReturnType::Default => quote! {},
+        ReturnType::Type(..) => {
+            quote! { ::std::result::Result::Ok(::std::default::Default::default()) }
+        }
+    };
+    let body = &testfn.block;
+    testfn.block = parse_quote!({
+        // the harness names tests by their module path, without the crate name
+        let #testpath = match module_path!().split_once("::") {
+            Some((_, module)) => format!("{}::{}", module, #testname),
+            None => #testname.to_string(),
+        };
+        if ::std::env::var("PYO3_TESTING_ISOLATED").is_ok_and(|isolated| isolated == #testpath) #body
+        else {
+            let #output = ::std::process::Command::new(
+                ::std::env::current_exe().expect("Failed to find the test executable"),
+            )
+            .args([&#testpath, "--exact", "--include-ignored", "--nocapture"])
+            .env("PYO3_TESTING_ISOLATED", &#testpath)
+            .output()
+            .expect("Failed to run isolated test");
+            let #stdout = String::from_utf8_lossy(&#output.stdout);
+            print!("{}", #stdout);
+            eprint!("{}", String::from_utf8_lossy(&#output.stderr));
+            assert!(
+                #output.status.success(),
+                "{} failed in its isolated process",
+                #testpath
+            );
+            assert!(
+                #stdout.contains("test result: ok. 1 passed;"),
+                "{} did not run in its isolated process",
+                #testpath
+            );
+            #passed
+        }
+    });
+}
 
-                    // get the python moduledef from the hidden rust module
-                    let #o3_pymoduledefidents = &#o3_moduleidents::_PYO3_DEF;
+/// Testcases may return a value, e.g. `PyResult<()>`, which is passed through from the closure run
+/// by `Python::attach`. Returns the tokens needed to bind the result, annotate the closure's return
+/// type and then handle the result.
+///
+/// If the testcase returns a `PyResult` and this is an `Err` the test panics with the python
+/// traceback, rather than just the `Debug` representation of the `PyErr`.
+fn check_result(signature: &Signature) -> (TokenStream2, TokenStream2, TokenStream2) {
+    let ReturnType::Type(_, returntype) = &signature.output else {
+        return (quote! {}, quote! {}, quote! { ; });
+    };
+    if !is_pyresult(returntype) {
+        return (quote! {}, quote! { -> #returntype }, quote! {});
+    }
+    let result = Ident::new("result", Span::mixed_site());
+    let error = Ident::new("error", Span::mixed_site());
+    let py = Ident::new("py", Span::mixed_site());
+    let testname = signature.ident.to_string();
+    let traceback = format_exception(&error, &py);
+    (
+        quote! { let #result = },
+        quote! { -> #returntype },
+        quote! {
+            ;
+            if let Err(#error) = &#result {
+                ::pyo3::Python::attach(|#py| {
+                    panic!("{} raised an exception:\n\n{}", #testname, #traceback)
+                });
+            }
+            #result
+        },
+    )
+}
 
-                    // manually create unbound python module
-                    // (temp rust binding to avoid dropping too early)
-                    let #o3_pymoduleidents = #o3_pymoduledefidents
-                        .make_module(py)
-                        .unwrap();
-                    // and then bind module to py
-                    let #o3_pymoduleidents = #o3_pymoduleidents.bind(py);
-
-                    // insert module into sys_modules
-                    sys_modules
-                        .set_item(#py_modulenames, #o3_pymoduleidents)
-                        .expect(#py_ModuleNotFoundErrormsgs);
-
-                    // and get it back - cannot fail as we just put it there
-                    let #py_moduleidents = sys_modules.get_item(#py_modulenames).unwrap().unwrap();
-                )*
-
-                #( // for each function to import
-
-                    // assign each wrapped function to a rust Ident of the same name
-                    let #py_functionidents = #py_moduleswithfnsidents
-                        .getattr(#py_functionnames)
-                        .expect(#py_AttributeErrormsgs);
-
-                    // create call macros last, so they have access to the py_functionidents we create
-                    macro_rules! #py_macroidents {
-                        ($($arg:tt),+) => {
-                            #py_functionidents
-                            .call1(($($arg,)+))
-                            .unwrap()
-                            .extract()
-                            .unwrap()
-                        };
-                        (*$args:ident) => {
-                            #py_functionidents
-                            .call1($args)
-                            .unwrap()
-                            .extract()
-                            .unwrap()
-                        };
-                        () => {
-                            #py_functionidents
-                            .call0()
-                            .unwrap()
-                            .extract()
-                            .unwrap()
-                        };
-                    };
-                )*
+/// Whether a type is a `PyResult<T>` or a `Result<T, PyErr>`
+fn is_pyresult(returntype: &Type) -> bool {
+    let Type::Path(returntype) = returntype else {
+        return false;
+    };
+    let Some(segment) = returntype.path.segments.last() else {
+        return false;
+    };
+    if segment.ident == "PyResult" {
+        return true;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) if segment.ident == "Result" => {
+            arguments.args.iter().nth(1).is_some_and(|errortype| {
+                matches!(errortype, GenericArgument::Type(Type::Path(errortype))
+                    if errortype.path.segments.last().is_some_and(|segment| segment.ident == "PyErr"))
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Passes the names of the generated call macros on to any `with_py_raises!` invocations in the
+/// testcase, so that they can be routed through the fallible `try_` variants.
+///
+/// The class constructor macros are passed separately, as they return a typed `Bound` rather
+/// than extracting their result.
+struct CallMacroRouter<'a> {
+    call_macros: &'a [Ident],
+    class_macros: &'a [Ident],
+}
 
-                #(#testfn_statements)*
-            });
+impl VisitMut for CallMacroRouter<'_> {
+    fn visit_macro_mut(&mut self, mac: &mut Macro) {
+        if mac
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "with_py_raises")
+        {
+            let call_macros = self.call_macros;
+            let tokens = &mac.tokens;
+            mac.tokens = if self.class_macros.is_empty() {
+                quote! { @call_macros(#(#call_macros),*) #tokens }
+            } else {
+                let class_macros = self.class_macros;
+                quote! { @call_macros(#(#call_macros),*) @class_macros(#(#class_macros),*) #tokens }
+            };
         }
-    );
+        visit_mut::visit_macro_mut(self, mac);
+    }
+}
+
+/// Routes every call macro in the statements of a case which is expected to raise to its fallible
+/// `try_` equivalent and returns any error with `?`. Call macros inside other macros, e.g.
+/// `assert_eq!`, are not visible and so still panic.
+struct TryRouter<'a, 'b> {
+    router: &'a CallMacroRouter<'b>,
+    /// Whether the statement can raise, i.e. contains a routed call macro or `?`
+    raises: bool,
+}
+
+impl TryRouter<'_, '_> {
+    fn route(&self, mac: &Macro) -> Option<Expr> {
+        let args = &mac.tokens;
+        if let Some(call_macro) = mac.path.get_ident() {
+            if self.router.call_macros.contains(call_macro)
+                || self.router.class_macros.contains(call_macro)
+            {
+                let try_call_macro = format_ident!("try_{}", call_macro);
+                return Some(parse_quote! { #try_call_macro!(#args)? });
+            }
+        }
+        if mac
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "py_call")
+        {
+            return Some(parse_quote! { ::pyo3_testing::pyo3trycall!(#args)? });
+        }
+        None
+    }
+}
 
-    testfn.attrs.append(&mut testcase.otherattributes);
+impl VisitMut for TryRouter<'_, '_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        visit_mut::visit_expr_mut(self, expr);
+        match expr {
+            Expr::Macro(call) => {
+                if let Some(routed) = self.route(&call.mac) {
+                    *expr = routed;
+                    self.raises = true;
+                }
+            }
+            Expr::Try(_) => self.raises = true,
+            _ => (),
+        }
+    }
 
-    testfn.into_token_stream()
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        visit_mut::visit_stmt_mut(self, stmt);
+        if let Stmt::Macro(call) = stmt {
+            if let Some(routed) = self.route(&call.mac) {
+                *stmt = Stmt::Expr(routed, Some(call.semi_token.unwrap_or_default()));
+                self.raises = true;
+            }
+        }
+    }
 }
 
 #[allow(clippy::non_minimal_cfg)]
@@ -289,13 +1538,47 @@ mod tests {
                     let sys = PyModule::import(py, "sys").unwrap();
                     let sys_modules: Bound<'_, PyDict> =
                         sys.getattr("modules").unwrap().cast_into().unwrap();
+                    struct SysModulesGuard<'py> {
+                        sys_modules: Bound<'py, PyDict>,
+                        registered: Vec<(String, Bound<'py, PyAny>, Option<Bound<'py, PyAny> >)>,
+                    }
+                    impl<'py> SysModulesGuard<'py> {
+                        fn register(
+                            &mut self,
+                            key: &str,
+                            create: impl FnOnce() -> PyResult<Bound<'py, PyAny> >,
+                        ) -> PyResult<Bound<'py, PyAny> > {
+                            let previous = self.sys_modules.get_item(key)?;
+                            let module = create()?;
+                            self.sys_modules.set_item(key, &module)?;
+                            self.registered.push((key.to_string(), module.clone(), previous));
+                            Ok(module)
+                        }
+                    }
+                    impl Drop for SysModulesGuard<'_> {
+                        fn drop(&mut self) {
+                            for (key, module, previous) in self.registered.drain(..).rev() {
+                                let current = self.sys_modules.get_item(&key).ok().flatten();
+                                if current.is_some_and(|current| current.is(&module)) {
+                                    let _ = match previous {
+                                        Some(previous) => self.sys_modules.set_item(&key, previous),
+                                        None => self.sys_modules.del_item(&key),
+                                    };
+                                }
+                            }
+                        }
+                    }
+                    let mut sys_modules_guard = SysModulesGuard {
+                        sys_modules: sys_modules.clone(),
+                        registered: Vec::new(),
+                    };
                     let py_fizzbuzzo3_pymoduledef = &py_fizzbuzzo3::_PYO3_DEF;
                     let py_fizzbuzzo3_pymodule = py_fizzbuzzo3_pymoduledef
                         .make_module(py)
                         .unwrap();
                     let py_fizzbuzzo3_pymodule = py_fizzbuzzo3_pymodule.bind(py);
-                    sys_modules
-                        .set_item("fizzbuzzo3", py_fizzbuzzo3_pymodule)
+                    sys_modules_guard
+                        .register("fizzbuzzo3", | | Ok(py_fizzbuzzo3_pymodule.clone().into_any()))
                         .expect("Failed to import fizzbuzzo3");
                     let fizzbuzzo3 = sys_modules.get_item("fizzbuzzo3").unwrap().unwrap();
                     let foo_o3_pymoduledef = &foo_o3::_PYO3_DEF;
@@ -303,60 +1586,38 @@ mod tests {
                         .make_module(py)
                         .unwrap();
                     let foo_o3_pymodule = foo_o3_pymodule.bind(py);
-                    sys_modules
-                        .set_item("pyfoo", foo_o3_pymodule)
+                    sys_modules_guard
+                        .register("pyfoo", | | Ok(foo_o3_pymodule.clone().into_any()))
                         .expect("Failed to import pyfoo");
                     let pyfoo = sys_modules.get_item("pyfoo").unwrap().unwrap();
                     let fizzbuzz = fizzbuzzo3
                         .getattr("fizzbuzz")
                         .expect("Failed to get fizzbuzz function");
+                    #[allow(unused_macros)]
                     macro_rules! fizzbuzz {
-                        ($($arg:tt),+) => {
-                            fizzbuzz
-                            .call1(($($arg,)+))
-                            .unwrap()
-                            .extract()
-                            .unwrap()
-                        };
-                        (*$args:ident) => {
-                            fizzbuzz
-                            .call1($args)
-                            .unwrap()
-                            .extract()
-                            .unwrap()
+                        ($($arg:tt)*) => {
+                            ::pyo3_testing::pyo3call!(fizzbuzz($($arg)*))
                         };
-                        () => {
-                            fizzbuzz
-                            .call0()
-                            .unwrap()
-                            .extract()
-                            .unwrap()
+                    };
+                    #[allow(unused_macros)]
+                    macro_rules! try_fizzbuzz {
+                        ($($arg:tt)*) => {
+                            ::pyo3_testing::pyo3trycall!(fizzbuzz($($arg)*))
                         };
                     };
                     let pybar = pyfoo
                         .getattr("pybar")
                         .expect("Failed to get pybar function");
+                    #[allow(unused_macros)]
                     macro_rules! pybar {
-                        ($($arg:tt),+) => {
-                            pybar
-                            .call1(($($arg,)+))
-                            .unwrap()
-                            .extract()
-                            .unwrap()
+                        ($($arg:tt)*) => {
+                            ::pyo3_testing::pyo3call!(pybar($($arg)*))
                         };
-                        (*$args:ident) => {
-                            pybar
-                            .call1($args)
-                            .unwrap()
-                            .extract()
-                            .unwrap()
-                        };
-                        () => {
-                            pybar
-                            .call0()
-                            .unwrap()
-                            .extract()
-                            .unwrap()
+                    };
+                    #[allow(unused_macros)]
+                    macro_rules! try_pybar {
+                        ($($arg:tt)*) => {
+                            ::pyo3_testing::pyo3trycall!(pybar($($arg)*))
                         };
                     };
                     assert!(true)
@@ -368,4 +1629,255 @@ mod tests {
 
         assert_eq!(output.to_string(), expected.to_string());
     }
+
+    #[test]
+    fn test_route_call_macros() {
+        let mut statement: Stmt = parse_quote! {
+            with_py_raises!(PyTypeError, { addone!("4") });
+        };
+        let call_macros = [format_ident!("addone"), format_ident!("double")];
+        CallMacroRouter {
+            call_macros: &call_macros,
+            class_macros: &[],
+        }
+        .visit_stmt_mut(&mut statement);
+        let expected: Stmt = parse_quote! {
+            with_py_raises!(@call_macros(addone, double) PyTypeError, { addone!("4") });
+        };
+        assert_eq!(
+            statement.into_token_stream().to_string(),
+            expected.into_token_stream().to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_aliases() {
+        let import: Pyo3Import = parse_quote! {
+            py_adders: from adders import type as kind
+        };
+        assert_eq!(import.py_module.ident, format_ident!("adders"));
+        assert_eq!(import.py_functions.len(), 1);
+        assert_eq!(import.py_functions[0].name, "type");
+        assert_eq!(import.py_functions[0].ident, format_ident!("kind"));
+
+        let import: Pyo3Import = parse_quote! {
+            py_adders: import adders as maths
+        };
+        assert_eq!(import.py_module.name, "adders");
+        assert_eq!(import.py_module.ident, format_ident!("maths"));
+        assert_eq!(import.py_functions, vec![]);
+    }
+
+    #[test]
+    fn test_parse_multiple_functions() {
+        let expected = vec![
+            PythonName::new("addone".to_string(), format_ident!("addone")),
+            PythonName::new("double".to_string(), format_ident!("times2")),
+            PythonName::new("add".to_string(), format_ident!("add")),
+        ];
+        let import: Pyo3Import = parse_quote! {
+            py_adders: from adders import addone, double as times2, add
+        };
+        assert_eq!(import.py_functions, expected);
+        let import: Pyo3Import = parse_quote! {
+            py_adders: from adders import (addone, double as times2, add,)
+        };
+        assert_eq!(import.py_functions, expected);
+    }
+
+    #[test]
+    fn test_parse_dotted_modules() {
+        let import: Pyo3Import = parse_quote! {
+            py_maths: from maths.core.arithmetic import addone
+        };
+        assert_eq!(import.py_module.name, "maths.core.arithmetic");
+        assert_eq!(import.py_module.ident, format_ident!("arithmetic"));
+
+        let import: Pyo3Import = parse_quote! {
+            py_maths: import maths.core
+        };
+        assert_eq!(import.py_module.name, "maths.core");
+        assert_eq!(import.py_module.ident, format_ident!("core"));
+
+        let import: Pyo3Import = parse_quote! {
+            py_maths: import maths.type as kind
+        };
+        assert_eq!(import.py_module.name, "maths.type");
+        assert_eq!(import.py_module.ident, format_ident!("kind"));
+    }
+
+    #[test]
+    fn test_parse_star() {
+        let import: Pyo3Import = parse_quote! {
+            py_adders: from adders import *
+        };
+        assert_eq!(import.py_functions, vec![]);
+        assert_eq!(import.py_namespace, Some(format_ident!("adders_ns")));
+    }
+
+    #[test]
+    fn test_parse_classes() {
+        let import: Pyo3Import = parse_quote! {
+            py_shapes: from shapes import area, class Square, class Circle as Round: shapes::PyCircle
+        };
+        assert_eq!(
+            import.py_functions,
+            vec![PythonName::new("area".to_string(), format_ident!("area"))]
+        );
+        let expected = vec![
+            PythonClass {
+                py_name: PythonName::new("Square".to_string(), format_ident!("Square")),
+                o3_type: parse_quote!(Square),
+            },
+            PythonClass {
+                py_name: PythonName::new("Circle".to_string(), format_ident!("Round")),
+                o3_type: parse_quote!(shapes::PyCircle),
+            },
+        ];
+        assert_eq!(import.py_classes, expected);
+    }
+
+    #[test]
+    fn test_parse_python_module() {
+        let import: Pyo3Import = parse_quote! {
+            from decimal import Decimal
+        };
+        assert_eq!(import.source, ModuleSource::Python);
+        assert_eq!(import.py_module.name, "decimal");
+
+        let import: Pyo3Import = parse_quote! {
+            import os.path
+        };
+        assert_eq!(import.source, ModuleSource::Python);
+        assert_eq!(import.py_module.ident, format_ident!("path"));
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let import: Pyo3Import = parse_quote! {
+            file "tests/py/helpers.py": from helpers import make_callback
+        };
+        assert_eq!(
+            import.source,
+            ModuleSource::File(parse_quote!("tests/py/helpers.py"))
+        );
+        assert_eq!(import.py_module.name, "helpers");
+    }
+
+    #[test]
+    fn test_parse_code() {
+        let code: Pyo3Code = parse_quote! {
+            name = "helpers", r#"def two(): return 2"#
+        };
+        assert_eq!(code.name.value(), "helpers");
+        assert_eq!(code.code.value(), "def two(): return 2");
+    }
+
+    #[test]
+    fn test_dedent() {
+        let code = "
+            def two():
+                return 2
+
+            x = two()
+        ";
+        assert_eq!(dedent(code), "\ndef two():\n    return 2\n\nx = two()\n");
+    }
+
+    #[test]
+    fn test_is_pyresult() {
+        assert!(is_pyresult(&parse_quote!(PyResult<()>)));
+        assert!(is_pyresult(&parse_quote!(pyo3::PyResult<()>)));
+        assert!(is_pyresult(&parse_quote!(Result<(), PyErr>)));
+        assert!(!is_pyresult(&parse_quote!(Result<(), String>)));
+        assert!(!is_pyresult(&parse_quote!(anyhow::Result<()>)));
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options: Pyo3TestOptions = parse2(quote! {}).unwrap();
+        assert_eq!(options, Pyo3TestOptions { isolated: false });
+        let options: Pyo3TestOptions = parse2(quote! { isolated }).unwrap();
+        assert_eq!(options, Pyo3TestOptions { isolated: true });
+        assert!(parse2::<Pyo3TestOptions>(quote! { isolate }).is_err());
+    }
+
+    #[test]
+    fn test_parse_params() {
+        let params: Pyo3Params = parse2(quote! {
+            input, expected; (1, 2), #[ignore] raises: ("x", err PyTypeError)
+        })
+        .unwrap();
+        assert_eq!(
+            params,
+            Pyo3Params {
+                names: vec![parse_quote!(input), parse_quote!(expected)],
+                cases: vec![
+                    Pyo3Case {
+                        attrs: vec![],
+                        id: None,
+                        values: vec![
+                            ParamValue::Value(parse_quote!(1)),
+                            ParamValue::Value(parse_quote!(2))
+                        ],
+                    },
+                    Pyo3Case {
+                        attrs: vec![parse_quote!(#[ignore])],
+                        id: Some(parse_quote!(raises)),
+                        values: vec![
+                            ParamValue::Value(parse_quote!("x")),
+                            ParamValue::Err(parse_quote!(PyTypeError))
+                        ],
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_params_single_parameter() {
+        let params: Pyo3Params =
+            parse2(quote! { input; 1, (2, 3), three: 3, std::f64::consts::PI }).unwrap();
+        let cases: Vec<(Option<Ident>, Vec<ParamValue>)> = params
+            .cases
+            .into_iter()
+            .map(|case| (case.id, case.values))
+            .collect();
+        assert_eq!(
+            cases,
+            vec![
+                (None, vec![ParamValue::Value(parse_quote!(1))]),
+                (None, vec![ParamValue::Value(parse_quote!((2, 3)))]),
+                (
+                    Some(parse_quote!(three)),
+                    vec![ParamValue::Value(parse_quote!(3))]
+                ),
+                (
+                    None,
+                    vec![ParamValue::Value(parse_quote!(std::f64::consts::PI))]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_case_names() {
+        let params: Pyo3Params = parse2(quote! {
+            input, expected;
+            (1, 2.5), ("Hello, World!", true), (-1, err pyo3::exceptions::PyTypeError),
+            named: (x, ""), (1, 2.5)
+        })
+        .unwrap();
+        let names: Vec<String> = params.case_names().iter().map(Ident::to_string).collect();
+        assert_eq!(
+            names,
+            [
+                "case_1_2_5",
+                "case_hello_world_true",
+                "case_input3_pytypeerror",
+                "named",
+                "case_1_2_5_5"
+            ]
+        );
+    }
 }
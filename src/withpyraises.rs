@@ -2,15 +2,19 @@
 /// for unit testing and easier refactoring.
 ///
 /// [1]: https://docs.pytest.org/en/latest/getting-started.html#assert-that-a-certain-exception-is-raised
-use proc_macro2::TokenStream as TokenStream2;
-use quote::ToTokens;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, ToTokens};
 use syn::{
+    parenthesized,
     parse::{Parse, ParseStream},
     parse2, parse_quote,
-    token::Comma,
-    Block, Ident, Stmt,
+    punctuated::Punctuated,
+    token::{Comma, Paren},
+    Block, Expr, Ident, LitStr, Path, Stmt, Token,
 };
 
+use crate::pyerr::format_exception;
+
 /// Parses the macro invocation contents as a with raises statement and then returns the
 /// required code segment to check that the expected error is raised.
 pub fn impl_with_py_raises(input: TokenStream2) -> TokenStream2 {
@@ -24,15 +28,29 @@ pub fn impl_with_py_raises(input: TokenStream2) -> TokenStream2 {
 /// Represents a well-formed `with pytest.raises`-like statement.
 ///
 /// In order to be correctly parsed this should be in the form of
-/// `Error Type` `Comma: [,]` `{block in braces}`  
+/// `Error Type` `Comma: [,]` `{block in braces}` or
+/// `Error Type` `Comma: [,]` `match = "regex"` `Comma: [,]` `{block in braces}`
+/// where `Error Type` may also be a tuple of types: `(Error Type, Error Type)`
 #[derive(Debug, PartialEq)]
 struct WithRaisesStmt {
-    /// The error type, this must be the ident of a valid rust error type which is already in scope.
+    /// The names of the call macros generated by `#[pyo3test]`. These are not provided by the user
+    /// but added by `#[pyo3test]` in the form `@call_macros(name, ...)` before the error type.
+    call_macros: Vec<Ident>,
+    /// The names of the class constructor macros generated by `#[pyo3test]`, which are also routed
+    /// to their `try_` equivalents, but already return a typed `Bound`. Added by `#[pyo3test]` in
+    /// the form `@class_macros(name, ...)` after any `@call_macros`.
+    class_macros: Vec<Ident>,
+    /// The error type(s), given either as a single path (e.g. `PyTypeError` or
+    /// `pyo3::exceptions::PyTypeError`) or a tuple of paths (e.g. `(PyTypeError, PyValueError)`),
+    /// mirroring python's `except (A, B):`. Each must be a valid rust error type.
     /// The error type must implement `std::from::From<E> for PyErr`, which all pyo3 errors do.
     /// See [pyo3 - Error handling][1] for details on implementing this for custom error types.
     ///
     /// [1]: https://pyo3.rs/v0.21.2/function/error-handling#custom-rust-error-types
-    err: Ident,
+    err: Vec<Path>,
+    /// An optional regular expression which must match the `str()` of the raised exception, using
+    /// python's `re.search`, just like `pytest.raises(..., match=...)`.
+    pattern: Option<LitStr>,
     /// A valid rust code block which returns a `PyResult` and is expected to result in the
     /// specified `err`. The same guidance applies here as it does to with `pytest.raises` blocks
     /// in python: keep this as short as possible to be sure you are checking the right thing.
@@ -44,17 +62,86 @@ impl Parse for WithRaisesStmt {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         let error_example =
         "\nCorrect format for with_py_raises is: `Error Type` `Comma: [,]` `{block in braces}`\n\
+        or: `Error Type` `Comma: [,]` `match = \"regex\"` `Comma: [,]` `{block in braces}`\n\
         E.g.: `with_py_raises!(PyTypeError, { addone.call1((\"4\",)) })`";
-        let err: Ident = input.parse()?;
+        let mut call_macros = Vec::<Ident>::new();
+        let mut class_macros = Vec::<Ident>::new();
+        while input.peek(Token![@]) {
+            let _at: Token![@] = input.parse()?;
+            let marker: Ident = input.parse()?;
+            let names;
+            parenthesized!(names in input);
+            let names = Punctuated::<Ident, Comma>::parse_terminated(&names)?;
+            if marker == "call_macros" {
+                call_macros.extend(names);
+            } else if marker == "class_macros" {
+                class_macros.extend(names);
+            } else {
+                return Err(syn::Error::new(
+                    marker.span(),
+                    "Expected `@call_macros(...)` or `@class_macros(...)`, these are only added by #[pyo3test]",
+                ));
+            }
+        }
+        // the brackets around a tuple of error types, so that errors can point at the whole tuple
+        let mut brackets = None;
+        let err: Vec<Path> = if input.peek(Paren) {
+            let errtypes;
+            let errbrackets = parenthesized!(errtypes in input);
+            let errtypes = Punctuated::<Path, Comma>::parse_terminated(&errtypes)?;
+            if errtypes.is_empty() {
+                return Err(syn::Error::new(
+                    errbrackets.span.join(),
+                    "Expected at least one error type in the brackets:".to_string() + error_example,
+                ));
+            }
+            brackets = Some(errbrackets);
+            errtypes.into_iter().collect()
+        } else {
+            vec![input.parse()?]
+        };
         let _comma: Comma = match input.parse() {
             Ok(comma) => comma,
             Err(_) => {
-                return Err(syn::Error::new(
-                    err.span(),
-                    "Expected a comma (`,`) after this:".to_string() + error_example,
-                ))
+                let message = "Expected a comma (`,`) after this:".to_string() + error_example;
+                return Err(match brackets {
+                    Some(brackets) => syn::Error::new(brackets.span.join(), message),
+                    None => syn::Error::new_spanned(&err[0], message),
+                });
             }
         };
+        let mut pattern = None;
+        if input.peek(Token![match]) {
+            let matchkeyword: Token![match] = input.parse()?;
+            let _equals: Token![=] = match input.parse() {
+                Ok(equals) => equals,
+                Err(_) => {
+                    return Err(syn::Error::new(
+                        matchkeyword.span,
+                        "Expected an equals sign (`=`) after this:".to_string() + error_example,
+                    ))
+                }
+            };
+            let regex: LitStr = match input.parse() {
+                Ok(regex) => regex,
+                Err(error) => {
+                    return Err(syn::Error::new(
+                        error.span(),
+                        "Expected a string literal (`\"regex\"`) here:".to_string() + error_example,
+                    ))
+                }
+            };
+            let _comma: Comma = match input.parse() {
+                Ok(comma) => comma,
+                Err(_) => {
+                    return Err(syn::Error::new(
+                        regex.span(),
+                        "Expected a comma (`,`) after this:".to_string() + error_example,
+                    ))
+                }
+            };
+            pattern = Some(regex);
+        }
         let block: Block = match input.parse() {
             Ok(block) => block,
             Err(error) => {
@@ -65,49 +152,225 @@ impl Parse for WithRaisesStmt {
                 ))
             }
         };
-        Ok(WithRaisesStmt { err, block })
+        Ok(WithRaisesStmt {
+            call_macros,
+            class_macros,
+            err,
+            pattern,
+            block,
+        })
     }
 }
 
-/// Take a WithRaisesStmt and return a TokenStream2 which panics if the expected error is raised
-/// and returns () otherwise.
+/// Take a WithRaisesStmt and return a TokenStream2 which panics unless the expected error is raised
+/// and otherwise evaluates to the caught `PyErr`, so that the test continues and can inspect it.
+///
+/// The panic messages include the python `repr` (or, if it is not a python object, the type) of the
+/// returned value (no error) or the python traceback of the actual error (wrong error).
+///
+/// If a `pattern` was given the message of the caught error must also match this.
+///
+/// If the block ends with one of the call macros generated by `#[pyo3test]`, or `py_call!`, this
+/// is replaced by the fallible equivalent, so that the error is returned rather than causing a
+/// panic.
 fn expand(withraisesstmt: WithRaisesStmt) -> TokenStream2 {
+    // mixed_site hygiene, so that we can never clash with any idents used in the block, including
+    // the user's own `py`
+    let py = Ident::new("py", Span::mixed_site());
+    let result = Ident::new("result", Span::mixed_site());
+    let value = Ident::new("value", Span::mixed_site());
+    let error = Ident::new("error", Span::mixed_site());
+    let message = Ident::new("message", Span::mixed_site());
+    let found = Ident::new("found", Span::mixed_site());
+    let regex_error = Ident::new("regex_error", Span::mixed_site());
     let err = withraisesstmt.err;
-    let block = withraisesstmt.block;
-    let expanded: Stmt = parse_quote! {
+    let mut block = withraisesstmt.block;
+    let mut extract = false;
+    if let Some(Stmt::Expr(Expr::Macro(tail), None)) = block.stmts.last_mut() {
+        let args = tail.mac.tokens.clone();
+        if let Some(call_macro) = tail.mac.path.get_ident() {
+            if withraisesstmt.call_macros.contains(call_macro) {
+                let try_call_macro = format_ident!("try_{}", call_macro);
+                *tail = parse_quote! {
+                    #try_call_macro!(#args)
+                };
+                extract = true;
+            } else if withraisesstmt.class_macros.contains(call_macro) {
+                let try_class_macro = format_ident!("try_{}", call_macro);
+                *tail = parse_quote! {
+                    #try_class_macro!(#args)
+                };
+            }
+        }
+        if tail
+            .mac
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "py_call")
+        {
+            *tail = parse_quote! {
+                ::pyo3_testing::pyo3trycall!(#args)
+            };
+            extract = true;
+        }
+        if extract {
+            // no extraction required, this also avoids the need for a type annotation
+            block = parse_quote! {
+                {
+                    let #result: ::pyo3::PyResult<::pyo3::Bound<'_, ::pyo3::PyAny>> = #block;
+                    #result
+                }
+            };
+        }
+    }
+    let regex_traceback = format_exception(&regex_error, &py);
+    let caught: Expr = match withraisesstmt.pattern {
+        None => parse_quote! { #error },
+        Some(pattern) => parse_quote! {
+            {
+                let #message = #error.value(#py).to_string();
+                let #found = match ::pyo3::types::PyModule::import(#py, "re")
+                    .and_then(|re| re.call_method1("search", (#pattern, &#message)))
+                {
+                    Ok(#found) => #found,
+                    Err(#regex_error) => panic!(
+                        "Invalid regex pattern {:?}, use `\\` to escape any special characters:\n\n{}",
+                        #pattern, #regex_traceback
+                    ),
+                };
+                if #found.is_none() {
+                    panic!(
+                        "Regex pattern did not match the exception message:\n- {}\n+ {}",
+                        #pattern, #message
+                    );
+                }
+                #error
+            }
+        },
+    };
+    let expected = err
+        .iter()
+        .map(|errtype| errtype.to_token_stream().to_string().replace(' ', ""))
+        .collect::<Vec<String>>()
+        .join(" or ");
+    let traceback = format_exception(&error, &py);
+    let returned = describe_value(&value);
+    // get our own python token, rather than relying on one being in scope
+    let expanded: Expr = parse_quote! {
         match #block {
-            Ok(_) => panic!("No Error"),
-            Err(error) if error.is_instance_of::<#err>(py) => return (),
-            Err(_) => panic!("Wrong Error"),
+            Ok(#value) => panic!(
+                "No Error: expected {} but the block returned {}",
+                #expected, #returned
+            ),
+            Err(#error) => ::pyo3::Python::attach(|#py| {
+                if #(#error.is_instance_of::<#err>(#py))||* {
+                    #caught
+                } else {
+                    panic!(
+                        "Wrong Error: expected {} but got {}\n\n{}",
+                        #expected,
+                        #error.get_type(#py),
+                        #traceback
+                    )
+                }
+            }),
         }
     };
     expanded.into_token_stream()
 }
 
+/// Returns an expression which describes the value bound to `value` as a `String`, without requiring
+/// it to implement `Debug`: the python `repr` of a `Bound` or anything else which converts into a
+/// python object, otherwise (e.g. a rust struct) its type.
+///
+/// Which of these applies depends on the type of the value, which a proc macro cannot know, so the
+/// expansion uses autoref specialization: method resolution picks the implementation with the fewest
+/// auto-refs whose bounds are satisfied, falling back to the type name which is always available.
+fn describe_value(value: &Ident) -> TokenStream2 {
+    quote! {
+        {
+            struct Returned<'a, T>(&'a T);
+            trait DescribeBound {
+                fn describe(&self) -> String;
+            }
+            impl<T> DescribeBound for &&Returned<'_, ::pyo3::Bound<'_, T>> {
+                fn describe(&self) -> String {
+                    ::pyo3::types::PyAnyMethods::repr(self.0.as_any())
+                        .map(|repr| repr.to_string())
+                        .unwrap_or_else(|_| "<unknown repr>".to_string())
+                }
+            }
+            trait DescribeIntoPyObject {
+                fn describe(&self) -> String;
+            }
+            impl<T> DescribeIntoPyObject for &Returned<'_, T>
+            where
+                for<'a, 'py> &'a T: ::pyo3::IntoPyObject<'py>,
+            {
+                fn describe(&self) -> String {
+                    ::pyo3::Python::attach(|py| {
+                        ::pyo3::IntoPyObjectExt::into_bound_py_any(self.0, py)
+                            .and_then(|object| ::pyo3::types::PyAnyMethods::repr(&object))
+                            .map(|repr| repr.to_string())
+                            .unwrap_or_else(|_| "<unknown repr>".to_string())
+                    })
+                }
+            }
+            trait DescribeType {
+                fn describe(&self) -> String;
+            }
+            impl<T> DescribeType for Returned<'_, T> {
+                fn describe(&self) -> String {
+                    format!("a {}", ::std::any::type_name::<T>())
+                }
+            }
+            (&&&Returned(&#value)).describe()
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use quote::quote;
 
     #[test]
     fn test_expansion() {
         let codeblock = parse_quote! {
             {addone.call1("4",)}
         };
-        let errortype = parse_quote! {
+        let errortype = vec![parse_quote! {
             PyTypeError
-        };
+        }];
         let invocation = WithRaisesStmt {
+            call_macros: vec![],
+            class_macros: vec![],
             err: errortype,
+            pattern: None,
             block: codeblock,
         };
+        let traceback = format_exception(&format_ident!("error"), &format_ident!("py"));
+        let returned = describe_value(&format_ident!("value"));
         let expected: TokenStream2 = quote! {
             match  {
                 addone.call1("4",)
             }  {
-                Ok(_) => panic!("No Error"),
-                Err(error) if error.is_instance_of::<PyTypeError>(py) => return (),
-                Err(_) => panic!("Wrong Error"),
+                Ok(value) => panic!(
+                    "No Error: expected {} but the block returned {}",
+                    "PyTypeError", #returned
+                ),
+                Err(error) => ::pyo3::Python::attach(|py| {
+                    if error.is_instance_of::<PyTypeError>(py) {
+                        error
+                    } else {
+                        panic!(
+                            "Wrong Error: expected {} but got {}\n\n{}",
+                            "PyTypeError",
+                            error.get_type(py),
+                            #traceback
+                        )
+                    }
+                }),
             }
         };
         assert_eq!(expand(invocation).to_string(), expected.to_string())
@@ -123,13 +386,183 @@ mod test {
         let codeblock = parse_quote! {
             {addone.call1("4",)}
         };
-        let errortype = parse_quote! {
+        let errortype = vec![parse_quote! {
             PyTypeError
-        };
+        }];
         let expected = WithRaisesStmt {
+            call_macros: vec![],
+            class_macros: vec![],
             err: errortype,
+            pattern: None,
             block: codeblock,
         };
         assert_eq!(input, expected);
     }
+
+    #[test]
+    fn test_call_macro() {
+        let input: WithRaisesStmt = parse_quote! {
+            @call_macros(addone, double) PyTypeError, {
+                addone!("4")
+            }
+        };
+        let traceback = format_exception(&format_ident!("error"), &format_ident!("py"));
+        let returned = describe_value(&format_ident!("value"));
+        let expected: Expr = parse_quote! {
+            match {
+                let result: ::pyo3::PyResult<::pyo3::Bound<'_, ::pyo3::PyAny>> = {
+                    try_addone!("4")
+                };
+                result
+            } {
+                Ok(value) => panic!(
+                    "No Error: expected {} but the block returned {}",
+                    "PyTypeError", #returned
+                ),
+                Err(error) => ::pyo3::Python::attach(|py| {
+                    if error.is_instance_of::<PyTypeError>(py) {
+                        error
+                    } else {
+                        panic!(
+                            "Wrong Error: expected {} but got {}\n\n{}",
+                            "PyTypeError",
+                            error.get_type(py),
+                            #traceback
+                        )
+                    }
+                }),
+            }
+        };
+        assert_eq!(
+            expand(input).to_string(),
+            expected.into_token_stream().to_string()
+        )
+    }
+
+    #[test]
+    fn test_unknown_marker() {
+        let input: syn::Result<WithRaisesStmt> = parse2(quote! {
+            @class_macro(Circle) PyTypeError, { Circle!("4") }
+        });
+        assert!(input.is_err());
+    }
+
+    #[test]
+    fn test_parse_match() {
+        let input: WithRaisesStmt = parse_quote! {
+            PyValueError, match = "cannot divide", {
+                divide!(1, 0)
+            }
+        };
+        assert_eq!(input.pattern, Some(parse_quote! { "cannot divide" }));
+    }
+
+    #[test]
+    fn test_match_expansion() {
+        let input: WithRaisesStmt = parse_quote! {
+            PyValueError, match = "cannot divide", {
+                divide.call1((1, 0))
+            }
+        };
+        let traceback = format_exception(&format_ident!("error"), &format_ident!("py"));
+        let returned = describe_value(&format_ident!("value"));
+        let regex_traceback = format_exception(&format_ident!("regex_error"), &format_ident!("py"));
+        let expected: Expr = parse_quote! {
+            match {
+                divide.call1((1, 0))
+            } {
+                Ok(value) => panic!(
+                    "No Error: expected {} but the block returned {}",
+                    "PyValueError", #returned
+                ),
+                Err(error) => ::pyo3::Python::attach(|py| {
+                    if error.is_instance_of::<PyValueError>(py) {
+                        {
+                            let message = error.value(py).to_string();
+                            let found = match ::pyo3::types::PyModule::import(py, "re")
+                                .and_then(|re| re.call_method1("search", ("cannot divide", &message)))
+                            {
+                                Ok(found) => found,
+                                Err(regex_error) => panic!(
+                                    "Invalid regex pattern {:?}, use `\\` to escape any special characters:\n\n{}",
+                                    "cannot divide", #regex_traceback
+                                ),
+                            };
+                            if found.is_none() {
+                                panic!(
+                                    "Regex pattern did not match the exception message:\n- {}\n+ {}",
+                                    "cannot divide", message
+                                );
+                            }
+                            error
+                        }
+                    } else {
+                        panic!(
+                            "Wrong Error: expected {} but got {}\n\n{}",
+                            "PyValueError",
+                            error.get_type(py),
+                            #traceback
+                        )
+                    }
+                }),
+            }
+        };
+        assert_eq!(
+            expand(input).to_string(),
+            expected.into_token_stream().to_string()
+        )
+    }
+
+    #[test]
+    fn test_parse_paths() {
+        let input: WithRaisesStmt = parse_quote! {
+            (pyo3::exceptions::PyTypeError, PyValueError), {
+                addone!("4")
+            }
+        };
+        let expected: Vec<Path> = vec![
+            parse_quote! { pyo3::exceptions::PyTypeError },
+            parse_quote! { PyValueError },
+        ];
+        assert_eq!(input.err, expected);
+    }
+
+    #[test]
+    fn test_multiple_errors_expansion() {
+        let input: WithRaisesStmt = parse_quote! {
+            (PyTypeError, PyValueError), {
+                addone.call1(("4",))
+            }
+        };
+        let traceback = format_exception(&format_ident!("error"), &format_ident!("py"));
+        let returned = describe_value(&format_ident!("value"));
+        let expected: Expr = parse_quote! {
+            match {
+                addone.call1(("4",))
+            } {
+                Ok(value) => panic!(
+                    "No Error: expected {} but the block returned {}",
+                    "PyTypeError or PyValueError", #returned
+                ),
+                Err(error) => ::pyo3::Python::attach(|py| {
+                    if error.is_instance_of::<PyTypeError>(py)
+                        || error.is_instance_of::<PyValueError>(py)
+                    {
+                        error
+                    } else {
+                        panic!(
+                            "Wrong Error: expected {} but got {}\n\n{}",
+                            "PyTypeError or PyValueError",
+                            error.get_type(py),
+                            #traceback
+                        )
+                    }
+                }),
+            }
+        };
+        assert_eq!(
+            expand(input).to_string(),
+            expected.into_token_stream().to_string()
+        )
+    }
 }
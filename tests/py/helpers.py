@@ -0,0 +1,12 @@
+"""Python helpers for the integration tests, loaded with `#[pyo3import(file ...)]`"""
+
+
+def make_callback(offset):
+    def callback(num):
+        return num + offset
+
+    return callback
+
+
+def apply(function, *args):
+    return function(*args)
@@ -0,0 +1,302 @@
+use std::cell::RefCell;
+
+use pyo3::{prelude::*, types::PyList};
+use pyo3_testing::{pyo3fixture, pyo3test};
+
+#[pyclass]
+struct Counter {
+    #[pyo3(get)]
+    count: usize,
+}
+
+#[pymethods]
+impl Counter {
+    #[new]
+    #[pyo3(signature = (start = 0))]
+    fn new(start: usize) -> Self {
+        Counter { count: start }
+    }
+
+    fn increment(&mut self) -> usize {
+        self.count += 1;
+        self.count
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "counters")]
+fn py_counters(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Counter>()?;
+    Ok(())
+}
+
+// Fixtures and teardowns run on the same thread as the testcase, so they can log to a thread local
+thread_local! {
+    static LOG: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn log(entry: &str) {
+    LOG.with_borrow_mut(|log| log.push(entry.to_string()));
+}
+
+fn take_log() -> Vec<String> {
+    LOG.with_borrow_mut(std::mem::take)
+}
+
+#[pyo3fixture]
+fn numbers(py: Python<'_>) -> Bound<'_, PyList> {
+    PyList::new(py, [1, 2, 3]).unwrap()
+}
+
+#[pyo3test]
+fn test_fixture(numbers: Bound<'_, PyList>) {
+    let total: usize = numbers.iter().map(|n| n.extract::<usize>().unwrap()).sum();
+    assert_eq!(total, 6);
+}
+
+#[pyo3test]
+fn test_fixture_elided_lifetime(numbers: Bound<PyList>) {
+    assert_eq!(numbers.len(), 3);
+}
+
+#[pyo3test]
+fn test_fixture_named_python_token(gil: Python<'_>, numbers: Bound<'_, PyList>) {
+    let expected = PyList::new(gil, [1, 2, 3]).unwrap();
+    assert!(numbers.eq(expected).unwrap());
+}
+
+#[pyo3fixture]
+fn implicit_python_token() -> Bound<'_, PyAny> {
+    py.eval(c"40 + 2", None, None).unwrap()
+}
+
+#[pyo3fixture]
+fn explicit_lifetime<'py>(py: Python<'py>) -> Bound<'py, PyAny> {
+    py.eval(c"'hello'", None, None).unwrap()
+}
+
+#[pyo3test]
+fn test_fixture_signatures(
+    implicit_python_token: Bound<'_, PyAny>,
+    explicit_lifetime: Bound<'_, PyAny>,
+) {
+    assert_eq!(implicit_python_token.extract::<isize>().unwrap(), 42);
+    assert_eq!(explicit_lifetime.extract::<String>().unwrap(), "hello");
+}
+
+#[pyo3fixture]
+fn counter(py: Python<'_>) -> Bound<'_, Counter> {
+    Bound::new(py, Counter::new(10)).unwrap()
+}
+
+#[pyo3test]
+fn test_pyclass_fixture(counter: Bound<'_, Counter>) {
+    assert_eq!(counter.borrow_mut().increment(), 11);
+}
+
+// Fixtures are called after the testcase's imports, so can use the imported modules
+#[pyo3fixture]
+fn imported_counter(py: Python<'_>) -> Bound<'_, PyAny> {
+    py.import("counters")
+        .unwrap()
+        .getattr("Counter")
+        .unwrap()
+        .call1((5,))
+        .unwrap()
+}
+
+#[pyo3test]
+#[pyo3import(py_counters: from counters import class Counter)]
+fn test_fixture_uses_imported_module(imported_counter: Bound<'_, PyAny>) {
+    assert!(imported_counter.is_instance_of::<Counter>());
+    let count: usize = imported_counter
+        .getattr("count")
+        .unwrap()
+        .extract()
+        .unwrap();
+    assert_eq!(count, 5);
+}
+
+#[pyo3fixture]
+fn doubled(numbers: Bound<'_, PyList>) -> Vec<usize> {
+    numbers
+        .iter()
+        .map(|n| n.extract::<usize>().unwrap() * 2)
+        .collect()
+}
+
+#[pyo3test]
+fn test_fixture_dependencies(doubled: Vec<usize>) {
+    assert_eq!(doubled, vec![2, 4, 6]);
+}
+
+// Arguments keep their `mut` or `ref`, both in testcases and in fixtures
+#[pyo3fixture]
+fn tripled(mut doubled: Vec<usize>) -> Vec<usize> {
+    doubled.iter_mut().for_each(|n| *n = *n / 2 * 3);
+    doubled
+}
+
+#[pyo3test]
+fn test_fixture_mut_and_ref(mut doubled: Vec<usize>, ref tripled: Vec<usize>) {
+    doubled.push(8);
+    assert_eq!(doubled, vec![2, 4, 6, 8]);
+    let tripled: &Vec<usize> = tripled;
+    assert_eq!(tripled, &vec![3, 6, 9]);
+}
+
+// Each fixture is called once per testcase, so the testcase and all the fixtures which depend on
+// it get the same value
+#[pyo3fixture]
+fn shared(py: Python<'_>) -> Bound<'_, PyList> {
+    PyList::empty(py)
+}
+
+#[pyo3fixture]
+fn appends_one(shared: Bound<'_, PyList>) -> Bound<'_, PyList> {
+    shared.append(1).unwrap();
+    shared
+}
+
+#[pyo3fixture]
+fn appends_two(shared: Bound<'_, PyList>, appends_one: Bound<'_, PyList>) -> usize {
+    appends_one.append(2).unwrap();
+    shared.len()
+}
+
+#[pyo3test]
+fn test_fixture_shared(
+    appends_two: usize,
+    shared: Bound<'_, PyList>,
+    appends_one: Bound<'_, PyList>,
+) {
+    assert_eq!(appends_two, 2);
+    assert!(shared.is(&appends_one));
+    assert_eq!(shared.extract::<Vec<usize>>().unwrap(), vec![1, 2]);
+}
+
+mod submodule {
+    use super::*;
+
+    #[pyo3test]
+    fn test_fixture_from_parent_module(doubled: Vec<usize>, numbers: Bound<'_, PyList>) {
+        assert_eq!(doubled.len(), numbers.len());
+    }
+}
+
+// Fixtures which log their setup and teardown, to check the order
+struct Resource(&'static str);
+
+impl Drop for Resource {
+    fn drop(&mut self) {
+        log(&format!("drop {}", self.0));
+    }
+}
+
+#[pyo3fixture]
+fn resource() -> Resource {
+    log("setup resource");
+    Resource("resource")
+}
+
+#[pyo3fixture]
+fn outer(py: Python<'_>) -> Bound<'_, PyAny> {
+    log("setup outer");
+    teardown! {
+        log("teardown outer");
+    }
+    py.None().into_bound(py)
+}
+
+#[pyo3fixture]
+fn inner(outer: Bound<'_, PyAny>) -> Bound<'_, PyAny> {
+    log("setup inner");
+    let value = outer.clone();
+    teardown! {
+        assert!(value.is_none());
+        log("teardown inner");
+    }
+    outer
+}
+
+#[pyo3test]
+#[ignore = "called by test_teardown"]
+fn logs_teardown(resource: Resource, inner: Bound<'_, PyAny>) {
+    assert_eq!(resource.0, "resource");
+    assert!(inner.is_none());
+    log("test");
+}
+
+#[test]
+fn test_teardown() {
+    take_log();
+    logs_teardown();
+    assert_eq!(
+        take_log(),
+        vec![
+            "setup resource",
+            "setup outer",
+            "setup inner",
+            "test",
+            "drop resource",
+            "teardown inner",
+            "teardown outer",
+        ]
+    );
+}
+
+// Also called by test_teardown_after_panic, to check that the teardowns still run
+#[pyo3test]
+#[should_panic(expected = "on purpose")]
+fn panics_before_teardown(inner: Bound<'_, PyAny>) {
+    assert!(inner.is_none());
+    panic!("on purpose");
+}
+
+#[test]
+fn test_teardown_after_panic() {
+    take_log();
+    std::panic::catch_unwind(panics_before_teardown).unwrap_err();
+    assert_eq!(
+        take_log(),
+        vec![
+            "setup outer",
+            "setup inner",
+            "teardown inner",
+            "teardown outer"
+        ]
+    );
+}
+
+#[pyo3fixture]
+fn fails_teardown(outer: Bound<'_, PyAny>) -> Bound<'_, PyAny> {
+    teardown! {
+        log("teardown fails_teardown");
+        panic!("teardown failed");
+    }
+    outer
+}
+
+// Also called by test_teardown_panics, to check the other teardowns still run
+#[pyo3test]
+#[should_panic(expected = "teardown failed")]
+fn panics_in_teardown(fails_teardown: Bound<'_, PyAny>) {
+    assert!(fails_teardown.is_none());
+}
+
+#[test]
+fn test_teardown_panics() {
+    take_log();
+    let panic = std::panic::catch_unwind(panics_in_teardown).unwrap_err();
+    assert_eq!(*panic.downcast_ref::<&str>().unwrap(), "teardown failed");
+    assert_eq!(
+        take_log(),
+        vec!["setup outer", "teardown fails_teardown", "teardown outer"]
+    );
+}
+
+#[test]
+fn test_compile_errors_pyo3fixture() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/invalid_pyo3fixtures.rs");
+}
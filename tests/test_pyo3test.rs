@@ -1,5 +1,11 @@
-use pyo3::prelude::*;
-use pyo3_testing::pyo3test;
+use std::collections::HashMap;
+
+use pyo3::{
+    exceptions::{PyAttributeError, PyTypeError, PyValueError},
+    prelude::*,
+    types::{PyDict, PyTuple},
+};
+use pyo3_testing::{py_call, pyo3test, with_py_raises};
 
 // The example from the Guide ...
 fn o3_addone(num: isize) -> isize {
@@ -30,6 +36,33 @@ fn py_zero() -> isize {
     0
 }
 
+#[pyfunction]
+#[pyo3(name = "increment", signature = (num, *, step = 1))]
+fn py_increment(num: isize, step: isize) -> isize {
+    num + step
+}
+
+#[pyfunction]
+#[pyo3(name = "total", signature = (*nums, **extras))]
+fn py_total(nums: &Bound<'_, PyTuple>, extras: Option<&Bound<'_, PyDict>>) -> PyResult<isize> {
+    let mut total = 0;
+    for num in nums.iter() {
+        total += num.extract::<isize>()?;
+    }
+    if let Some(extras) = extras {
+        for extra in extras.values() {
+            total += extra.extract::<isize>()?;
+        }
+    }
+    Ok(total)
+}
+
+#[pyfunction]
+#[pyo3(name = "type")]
+fn py_type(num: isize) -> String {
+    format!("{num} is an integer")
+}
+
 #[pymodule]
 #[pyo3(name = "adders")]
 fn py_adders(module: &Bound<'_, PyModule>) -> PyResult<()> {
@@ -37,6 +70,81 @@ fn py_adders(module: &Bound<'_, PyModule>) -> PyResult<()> {
     module.add_function(wrap_pyfunction!(py_double, module)?)?;
     module.add_function(wrap_pyfunction!(py_add, module)?)?;
     module.add_function(wrap_pyfunction!(py_zero, module)?)?;
+    module.add_function(wrap_pyfunction!(py_increment, module)?)?;
+    module.add_function(wrap_pyfunction!(py_total, module)?)?;
+    module.add_function(wrap_pyfunction!(py_type, module)?)?;
+    Ok(())
+}
+
+// A package with nested submodules: `maths.core.arithmetic.addone`, which only exports `addone`
+#[pymodule]
+#[pyo3(name = "maths")]
+fn py_maths(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    let core = PyModule::new(module.py(), "core")?;
+    let arithmetic = PyModule::new(module.py(), "arithmetic")?;
+    arithmetic.add_function(wrap_pyfunction!(py_addone, &arithmetic)?)?;
+    arithmetic.add_function(wrap_pyfunction!(py_double, &arithmetic)?)?;
+    arithmetic.add("__all__", ["addone"])?;
+    core.add_submodule(&arithmetic)?;
+    module.add_submodule(&core)?;
+    Ok(())
+}
+
+#[pyclass(name = "Circle")]
+struct PyCircle {
+    #[pyo3(get)]
+    radius: f64,
+}
+
+#[pymethods]
+impl PyCircle {
+    #[new]
+    #[pyo3(signature = (radius = 1.0))]
+    fn new(radius: f64) -> Self {
+        PyCircle { radius }
+    }
+
+    fn diameter(&self) -> f64 {
+        self.radius * 2.0
+    }
+
+    #[pyo3(signature = (factor, *, offset = 0.0))]
+    fn scale(&self, factor: f64, offset: f64) -> f64 {
+        self.radius * factor + offset
+    }
+}
+
+#[pyclass]
+struct Square {
+    #[pyo3(get)]
+    side: f64,
+}
+
+#[pymethods]
+impl Square {
+    #[new]
+    fn new(side: f64) -> Self {
+        Square { side }
+    }
+}
+
+#[pyclass]
+struct Point(f64);
+
+#[pymethods]
+impl Point {
+    #[new]
+    fn new(x: f64) -> Self {
+        Point(x)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "shapes")]
+fn py_shapes(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyCircle>()?;
+    module.add_class::<Square>()?;
+    module.add_class::<Point>()?;
     Ok(())
 }
 
@@ -116,16 +224,15 @@ fn test_mixed_import_types() {
     assert_eq!(result, 4_isize);
 }
 
-#[cfg(false)]
 #[pyo3test]
 fn test_no_imports() {
-    let fun: Py<PyAny> = PyModule::from_code_bound(
+    let fun: Py<PyAny> = PyModule::from_code(
         py,
-        "def two():
-            return 2
-        ",
-        "",
-        "",
+        c"def two():
+    return 2
+",
+        c"",
+        c"",
     )
     .unwrap()
     .getattr("two")
@@ -135,6 +242,25 @@ fn test_no_imports() {
     assert_eq!(result, 2_isize)
 }
 
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_named_python_token(gil: Python<'_>) {
+    let result: isize = addone!(1);
+    assert_eq!(result, 2);
+    let sys = gil.import("sys").unwrap();
+    assert!(sys.getattr("modules").unwrap().contains("adders").unwrap());
+}
+
+#[pyo3test]
+fn test_no_imports_named_python_token(interpreter: Python) {
+    let result: isize = interpreter
+        .eval(c"1 + 1", None, None)
+        .unwrap()
+        .extract()
+        .unwrap();
+    assert_eq!(result, 2);
+}
+
 #[pyo3test]
 #[pyo3import(py_adders: from adders import add)]
 fn test_multiple_args() {
@@ -157,6 +283,691 @@ fn test_star_args() {
     assert_eq!(result, 3)
 }
 
+#[pyo3test]
+#[pyo3import(py_adders: from adders import increment)]
+fn test_kwargs() {
+    let result: isize = increment!(1, step = 2);
+    assert_eq!(result, 3);
+    let result: isize = increment!(1);
+    assert_eq!(result, 2);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import increment)]
+fn test_star_args_and_kwargs() {
+    let args = (1,);
+    let result: isize = increment!(*args, step = 5);
+    assert_eq!(result, 6)
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import total)]
+fn test_mixed_args_and_kwargs() {
+    let rest = vec![3, 4];
+    let mut opts = HashMap::new();
+    opts.insert("five", 5);
+    let result: isize = total!(1, 2, *rest, six = 6, **opts);
+    assert_eq!(result, 21);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import total)]
+fn test_python_args_and_kwargs() {
+    let rest = PyTuple::new(py, [2, 3]).unwrap();
+    let opts = PyDict::new(py);
+    opts.set_item("four", 4).unwrap();
+    let result: isize = total!(1, *rest, **opts);
+    assert_eq!(result, 10);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import increment)]
+fn test_repeated_kwargs() {
+    let mut opts = HashMap::new();
+    opts.insert("step", 10);
+    let result: PyResult<isize> = try_increment!(1, step = 2, **opts.clone());
+    let error = result.unwrap_err();
+    assert!(error.is_instance_of::<PyTypeError>(py));
+    assert_eq!(
+        error.value(py).to_string(),
+        "increment() got multiple values for keyword argument 'step'"
+    );
+    let result: PyResult<isize> = try_increment!(1, **opts.clone(), step = 2);
+    assert!(result.unwrap_err().is_instance_of::<PyTypeError>(py));
+    let result: PyResult<isize> = try_increment!(1, **opts.clone(), **opts);
+    assert!(result.unwrap_err().is_instance_of::<PyTypeError>(py));
+}
+
+#[pyo3test]
+#[should_panic(expected = "increment() got multiple values for keyword argument 'step'")]
+#[pyo3import(py_adders: from adders import increment)]
+fn test_repeated_kwargs_panics() {
+    let mut opts = HashMap::new();
+    opts.insert("step", 10);
+    let _result: isize = increment!(1, step = 2, **opts);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_try_call_macro() {
+    let result: PyResult<isize> = try_addone!(1);
+    assert_eq!(result.unwrap(), 2);
+    let result: PyResult<isize> = try_addone!("4");
+    assert!(result.unwrap_err().is_instance_of::<PyTypeError>(py));
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import increment)]
+fn test_try_call_macro_kwargs() {
+    let result: PyResult<isize> = try_increment!(1, step = 2);
+    assert_eq!(result.unwrap(), 3);
+    let result: PyResult<isize> = try_increment!(1, stride = 2);
+    assert!(result.unwrap_err().is_instance_of::<PyTypeError>(py));
+}
+
+#[pyo3test]
+#[should_panic(
+    expected = "addone('4') raised an exception:\n\nTypeError: 'str' object cannot be interpreted as an integer"
+)]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_call_macro_error_message() {
+    let _result: isize = addone!("4");
+}
+
+#[pyo3test]
+#[should_panic(expected = "increment(1, step='2') raised an exception:")]
+#[pyo3import(py_adders: from adders import increment)]
+fn test_call_macro_error_message_kwargs() {
+    let _result: isize = increment!(1, step = "2");
+}
+
+#[pyo3test]
+#[should_panic(expected = "addone(1) returned 2 which could not be extracted:\n\nTypeError")]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_call_macro_extraction_error_message() {
+    let _result: String = addone!(1);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone as add1)]
+fn test_import_function_as() {
+    let result: isize = add1!(1);
+    assert_eq!(result, 2);
+    let result: PyResult<isize> = try_add1!("4");
+    assert!(result.is_err());
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: import adders as maths)]
+fn test_import_module_as() {
+    let result: isize = maths
+        .getattr("addone")
+        .unwrap()
+        .call1((1_isize,))
+        .unwrap()
+        .extract()
+        .unwrap();
+    assert_eq!(result, 2);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import type as kind)]
+fn test_import_keyword_as() {
+    let result: String = kind!(1);
+    assert_eq!(result, "1 is an integer");
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone, double, add)]
+fn test_import_multiple_functions() {
+    let result: isize = addone!(1);
+    let result: isize = double!(result);
+    let result: isize = add!(result, 1);
+    assert_eq!(result, 5);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import (addone as add1, zero))]
+fn test_import_multiple_functions_in_brackets() {
+    let zero: isize = zero!();
+    let result: isize = add1!(zero);
+    assert_eq!(result, 1);
+}
+
+#[pyo3test]
+#[pyo3import(py_maths: from maths.core.arithmetic import addone)]
+fn test_import_from_submodule() {
+    let result: isize = addone!(1);
+    assert_eq!(result, 2);
+    assert!(arithmetic.is(py.import("maths.core.arithmetic").unwrap()));
+}
+
+#[pyo3test]
+#[pyo3import(py_maths: import maths.core)]
+fn test_import_submodule() {
+    let result: isize = core
+        .getattr("arithmetic")
+        .unwrap()
+        .getattr("addone")
+        .unwrap()
+        .call1((1_isize,))
+        .unwrap()
+        .extract()
+        .unwrap();
+    assert_eq!(result, 2);
+    let sys_modules = py.import("sys").unwrap().getattr("modules").unwrap();
+    assert!(sys_modules.contains("maths").unwrap());
+    assert!(sys_modules.contains("maths.core").unwrap());
+    assert!(core.is(sys_modules.get_item("maths.core").unwrap()));
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import *)]
+fn test_import_star() {
+    let result: isize = adders_ns!(addone(1));
+    let result: isize = adders_ns!(add(result, 2));
+    let result: isize = adders_ns!(increment(result, step = 3));
+    assert_eq!(result, 7);
+    assert!(!adders_ns.hasattr("__name__").unwrap());
+}
+
+#[pyo3test]
+#[pyo3import(py_maths: from maths.core.arithmetic import *)]
+fn test_import_star_respects_all() {
+    let result: isize = arithmetic_ns!(addone(1));
+    assert_eq!(result, 2);
+    assert!(!arithmetic_ns.hasattr("double").unwrap());
+    let result: PyResult<isize> = try_arithmetic_ns!(double(1));
+    assert!(result.unwrap_err().is_instance_of::<PyAttributeError>(py));
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import *)]
+fn test_try_import_star() {
+    let result: PyResult<isize> = try_adders_ns!(addone("4"));
+    assert!(result.unwrap_err().is_instance_of::<PyTypeError>(py));
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import *)]
+#[should_panic(expected = "adders does not export subtract")]
+fn test_import_star_missing_function() {
+    let _: isize = adders_ns!(subtract(1));
+}
+
+#[pyo3test]
+#[pyo3import(py_shapes: from shapes import class Circle: PyCircle)]
+fn test_import_class() {
+    let circle = Circle!(2.0);
+    assert_eq!(circle.borrow().radius, 2.0);
+    assert_eq!(circle.borrow().diameter(), 4.0);
+    let radius: f64 = circle.getattr("radius").unwrap().extract().unwrap();
+    assert_eq!(radius, 2.0);
+    assert_eq!(Circle!().borrow().radius, 1.0);
+    assert_eq!(Circle!(radius = 3.0).borrow().radius, 3.0);
+    assert!(circle.is_instance_of::<PyCircle>());
+}
+
+#[pyo3test]
+#[pyo3import(py_shapes: from shapes import class Square, class Circle as Round: PyCircle)]
+fn test_import_multiple_classes() {
+    let square = Square!(2.0);
+    let round = Round!(1.5);
+    assert_eq!(square.borrow().side, 2.0);
+    assert_eq!(round.borrow().radius, 1.5);
+}
+
+#[pyo3test]
+#[pyo3import(py_shapes: from shapes import class Point)]
+fn test_class_constructor_tuple_struct() {
+    let point = Point!(1.5);
+    assert_eq!(point.borrow().0, 1.5);
+    let point = try_Point!(2.5).unwrap();
+    assert_eq!(point.borrow().0, 2.5);
+    assert_eq!(Point(3.5).0, 3.5);
+}
+
+#[pyo3test]
+#[pyo3import(py_shapes: from shapes import class Square)]
+fn test_try_class_constructor() {
+    let square = try_Square!("2");
+    assert!(square.unwrap_err().is_instance_of::<PyTypeError>(py));
+}
+
+#[pyo3test]
+#[pyo3import(py_shapes: from shapes import class Square)]
+fn test_class_constructor_raises() {
+    with_py_raises!(PyTypeError, { Square!("2") });
+}
+
+#[pyo3test]
+#[should_panic(
+    expected = "No Error: expected PyTypeError but the block returned <builtins.Square object at"
+)]
+#[pyo3import(py_shapes: from shapes import class Square)]
+fn test_class_constructor_no_error_message() {
+    with_py_raises!(PyTypeError, { Square!(2.0) });
+}
+
+#[pyo3test]
+#[pyo3import(py_shapes: from shapes import class Circle: PyCircle)]
+fn test_py_call_method() {
+    let circle = Circle!(2.0);
+    let diameter: f64 = py_call!(circle.diameter());
+    assert_eq!(diameter, 4.0);
+    let scaled: f64 = py_call!(circle.scale(2.0, offset = 1.0));
+    assert_eq!(scaled, 5.0);
+    let kwargs = HashMap::from([("offset", 0.5)]);
+    let scaled: f64 = py_call!(circle.scale(*(3.0,), **kwargs));
+    assert_eq!(scaled, 6.5);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: import adders)]
+fn test_py_call_module_function() {
+    let result: isize = py_call!(adders.addone(1));
+    assert_eq!(result, 2);
+}
+
+#[pyo3test]
+#[pyo3import(py_shapes: from shapes import class Circle: PyCircle)]
+#[should_panic(expected = "circle.scale('2') raised an exception:\n\nTypeError")]
+fn test_py_call_error_message() {
+    let circle = Circle!(2.0);
+    let _: f64 = py_call!(circle.scale("2"));
+}
+
+#[pyo3test]
+#[pyo3import(py_shapes: from shapes import class Circle: PyCircle)]
+fn test_py_call_raises() {
+    let circle = Circle!(2.0);
+    with_py_raises!(PyTypeError, { py_call!(circle.scale("2")) });
+    with_py_raises!(PyAttributeError, { py_call!(circle.area()) });
+}
+
+#[pyo3test]
+#[pyo3import(from math import sqrt, floor)]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_import_python_module() {
+    let root: f64 = sqrt!(6.25);
+    assert_eq!(root, 2.5);
+    let rounded: isize = floor!(root);
+    let result: isize = addone!(rounded);
+    assert_eq!(result, 3);
+}
+
+#[pyo3test]
+#[pyo3import(from decimal import class Decimal: PyAny)]
+fn test_import_python_class() {
+    let value = Decimal!("1.10");
+    let doubled: Bound<'_, PyAny> = py_call!(value.__add__(&value));
+    assert_eq!(doubled.to_string(), "2.20");
+}
+
+#[pyo3test]
+#[pyo3import(import os.path)]
+fn test_import_python_submodule() {
+    let joined: String = py_call!(path.join("a", "b"));
+    assert_eq!(joined, format!("a{}b", std::path::MAIN_SEPARATOR));
+}
+
+#[pyo3test]
+#[pyo3import(file "tests/py/helpers.py": from helpers import make_callback)]
+fn test_import_python_file() {
+    let callback: Bound<'_, PyAny> = make_callback!(2);
+    let result: isize = py_call!(callback(1));
+    assert_eq!(result, 3);
+    assert!(helpers.is(py.import("helpers").unwrap()));
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3import(file "tests/py/helpers.py": from helpers import apply)]
+fn test_mix_python_file_and_rust_module() {
+    let result: isize = apply!(&addone, 1);
+    assert_eq!(result, 2);
+}
+
+#[pyo3test]
+#[pyo3code(
+    name = "snippets",
+    r#"
+    def two():
+        return 2
+"#
+)]
+#[pyo3import(from snippets import two)]
+fn test_inline_python() {
+    let result: isize = two!();
+    assert_eq!(result, 2);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3code(
+    name = "addthree",
+    r#"
+    from adders import addone, add
+
+    def addthree(num):
+        return add(addone(num), 2)
+"#
+)]
+#[pyo3import(from addthree import addthree)]
+fn test_inline_python_imports_rust_module() {
+    let result: isize = addthree!(1);
+    assert_eq!(result, 4);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_returns_pyresult() -> PyResult<()> {
+    let result: isize = try_addone!(1)?;
+    assert_eq!(result, 2);
+    Ok(())
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_returns_other_result() -> Result<(), Box<dyn std::error::Error>> {
+    let result: isize = try_addone!(1)?;
+    assert_eq!(result, 2);
+    Ok(())
+}
+
+// should_panic cannot be used with tests which return a Result, so the testcase is defined inside
+// this test, which checks the panic message. The harness cannot run it there
+#[test]
+#[allow(unnameable_test_items)]
+fn test_returns_pyresult_error() {
+    #[pyo3test]
+    #[pyo3import(py_adders: from adders import addone)]
+    fn returns_pyresult_error() -> PyResult<()> {
+        let _: isize = try_addone!("4")?;
+        Ok(())
+    }
+
+    let panic = std::panic::catch_unwind(returns_pyresult_error).unwrap_err();
+    let message = panic.downcast_ref::<String>().unwrap();
+    assert!(message.starts_with("returns_pyresult_error raised an exception:\n\n"));
+    assert!(message.contains("TypeError: 'str' object cannot be interpreted as an integer"));
+}
+
+fn check_sys_modules_restored(testcase: impl FnOnce(), suffix: &str) {
+    let [added, replaced, imported] =
+        ["leaky", "replaced", "imported"].map(|name| name.to_string() + suffix);
+    Python::initialize();
+    let original = Python::attach(|py| {
+        let original = PyModule::new(py, &replaced).unwrap().unbind();
+        let sys_modules = py.import("sys").unwrap().getattr("modules").unwrap();
+        sys_modules.set_item(&replaced, &original).unwrap();
+        original
+    });
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(testcase));
+    Python::attach(|py| {
+        let sys_modules = py.import("sys").unwrap().getattr("modules").unwrap();
+        assert!(!sys_modules.contains(&added).unwrap());
+        assert!(sys_modules.get_item(&replaced).unwrap().is(&original));
+        assert!(sys_modules.contains(&imported).unwrap());
+        sys_modules.del_item(&replaced).unwrap();
+        sys_modules.del_item(&imported).unwrap();
+    });
+}
+
+// The testcases which register modules in sys.modules are defined inside the tests which check
+// that only these changes are undone, so the harness cannot also run them alongside. The module
+// names are unique to these tests, as other tests share the interpreter
+#[test]
+#[allow(unnameable_test_items)]
+fn test_sys_modules_restored() {
+    #[pyo3test]
+    #[pyo3code(name = "leaky", r#"answer = 42"#)]
+    #[pyo3code(name = "replaced", r#"answer = 42"#)]
+    fn registers_modules() {
+        let sys_modules = py.import("sys").unwrap().getattr("modules").unwrap();
+        assert!(sys_modules.contains("leaky").unwrap());
+        // python modules imported by the testcase itself are left alone
+        sys_modules
+            .set_item("imported", PyModule::new(py, "imported").unwrap())
+            .unwrap();
+    }
+
+    check_sys_modules_restored(registers_modules, "");
+}
+
+#[test]
+#[allow(unnameable_test_items)]
+fn test_sys_modules_restored_after_panic() {
+    #[pyo3test]
+    #[pyo3code(name = "leaky_panic", r#"answer = 42"#)]
+    #[pyo3code(name = "replaced_panic", r#"answer = 42"#)]
+    fn panics_after_registering_modules() {
+        let sys_modules = py.import("sys").unwrap().getattr("modules").unwrap();
+        sys_modules
+            .set_item(
+                "imported_panic",
+                PyModule::new(py, "imported_panic").unwrap(),
+            )
+            .unwrap();
+        panic!("on purpose");
+    }
+
+    check_sys_modules_restored(panics_after_registering_modules, "_panic");
+}
+
+// If the module has been replaced since we registered it, e.g. by another test, it is left alone
+#[test]
+#[allow(unnameable_test_items)]
+fn test_sys_modules_replaced_by_others() {
+    #[pyo3test]
+    #[pyo3code(name = "reregistered", r#"answer = 42"#)]
+    fn reregisters_module() {
+        let sys_modules = py.import("sys").unwrap().getattr("modules").unwrap();
+        sys_modules
+            .set_item("reregistered", PyModule::new(py, "reregistered").unwrap())
+            .unwrap();
+    }
+
+    reregisters_module();
+    Python::attach(|py| {
+        let sys_modules = py.import("sys").unwrap().getattr("modules").unwrap();
+        let module = sys_modules.get_item("reregistered").unwrap();
+        assert!(!module.hasattr("answer").unwrap());
+        sys_modules.del_item("reregistered").unwrap();
+    });
+}
+
+// A module with per-module state, which is created when the module is first initialised and then
+// shared by every test which imports it in the same process
+#[pyfunction]
+#[pyo3(pass_module)]
+fn tick(module: &Bound<'_, PyModule>) -> PyResult<usize> {
+    let ticks = module.getattr("ticks")?.extract::<usize>()? + 1;
+    module.setattr("ticks", ticks)?;
+    Ok(ticks)
+}
+
+#[pymodule]
+#[pyo3(name = "clock")]
+fn py_clock(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add("ticks", 0)?;
+    module.add_function(wrap_pyfunction!(tick, module)?)?;
+    Ok(())
+}
+
+#[pyo3test]
+#[pyo3import(py_clock: from clock import tick)]
+fn test_not_isolated_shares_module_state() {
+    let ticks: usize = tick!();
+    assert!(ticks >= 1);
+}
+
+// Isolated tests are rerun in their own process, so the module is initialised afresh
+#[pyo3test(isolated)]
+#[pyo3import(py_clock: import clock)]
+#[pyo3import(py_clock: from clock import tick)]
+fn test_isolated() {
+    assert_eq!(
+        std::env::var("PYO3_TESTING_ISOLATED").unwrap(),
+        "test_isolated"
+    );
+    let ticks: usize = clock.getattr("ticks").unwrap().extract().unwrap();
+    assert_eq!(ticks, 0);
+    let ticks: usize = tick!();
+    assert_eq!(ticks, 1);
+}
+
+mod isolated {
+    use super::*;
+
+    #[pyo3test(isolated)]
+    fn test_isolated_in_module() -> PyResult<()> {
+        assert_eq!(
+            std::env::var("PYO3_TESTING_ISOLATED").unwrap(),
+            "isolated::test_isolated_in_module"
+        );
+        let result: isize = py.eval(c"1 + 1", None, None)?.extract()?;
+        assert_eq!(result, 2);
+        Ok(())
+    }
+}
+
+// The child process fails, as its panic does not match `expected`, so the parent panics instead
+#[pyo3test(isolated)]
+#[should_panic(expected = "test_isolated_failure failed in its isolated process")]
+fn test_isolated_failure() {
+    let result: isize = py.eval(c"1 + 1", None, None).unwrap().extract().unwrap();
+    assert_eq!(result, 3);
+}
+
+// Each case of a parametrised test is a separate test, named after its values:
+// `test_params::case_1_2`, ...
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3params(input, expected; (1, 2), (5, 6), (-1, 0))]
+fn test_params() {
+    let result: isize = addone!(input);
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn test_params_names() {
+    test_params::case_1_2();
+    test_params::case_5_6();
+    // `-1` is not a literal, so is named after the parameter and the number of the case
+    test_params::case_input3_0();
+}
+
+#[pyo3test]
+#[pyo3params(input; small: 1, large: 1_000_000)]
+fn test_params_ids() {
+    assert!(input > 0);
+}
+
+#[test]
+fn test_params_ids_names() {
+    test_params_ids::small();
+    test_params_ids::large();
+}
+
+// A case with an `err` value expects the testcase to raise the exception
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3params(input, expected; (1, 2), (5, 6), ("x", err PyTypeError))]
+fn test_params_mixed() {
+    let result: isize = addone!(input);
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn test_params_mixed_names() {
+    test_params_mixed::case_1_2();
+    test_params_mixed::case_x_pytypeerror();
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3params(input, expected; (1, 2), ((), err PyTypeError))]
+fn test_params_mixed_returns_result() -> PyResult<()> {
+    let result: isize = addone!(input);
+    assert_eq!(result, expected);
+    Ok(())
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3import(py_adders: from adders import *)]
+#[pyo3params(
+    first, second, expected;
+    (1, 2, 5), ("x", 2, err PyTypeError), (1, "x", err PyTypeError)
+)]
+fn test_params_mixed_raises_later() {
+    let first: isize = adders_ns!(addone(first));
+    let second: isize = addone!(second);
+    assert_eq!(first + second, expected);
+}
+
+// Each case can have attributes of its own, to check how the failing cases fail
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3params(
+    input, expected;
+    (1, 2),
+    #[should_panic(expected = "No Error: expected PyTypeError but the testcase did not raise it")]
+    no_error: (1, err PyTypeError),
+    #[should_panic(expected = "Wrong Error: expected PyValueError but got <class 'TypeError'>")]
+    wrong_error: ("x", err PyValueError),
+    // the error is checked before the assertion, which would fail
+    #[should_panic(expected = "No Error: expected PyTypeError but the testcase did not raise it")]
+    no_error_before_assertion: (5, err PyTypeError)
+)]
+fn test_params_mixed_failures() {
+    let result: isize = addone!(input);
+    assert_eq!(result, expected);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3params(input, expected; ("x", err PyTypeError), ((), err PyTypeError))]
+fn test_params_raises() {
+    with_py_raises!(expected, { addone!(input) });
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3params(input; 1, 2, 3)]
+fn test_params_single_parameter() {
+    let result: isize = addone!(input);
+    assert_eq!(result, input + 1);
+}
+
+#[pyo3test]
+#[pyo3import(py_shapes: from shapes import class Circle: PyCircle)]
+#[pyo3params(circle, diameter; (Circle!(1.0), 2.0), (Circle!(radius = 2.5), 5.0))]
+fn test_params_use_imports() {
+    let result: f64 = py_call!(circle.diameter());
+    assert_eq!(result, diameter);
+}
+
+#[pyo3test(isolated)]
+#[pyo3params(input; "a", "b")]
+fn test_params_isolated() {
+    let testpath = std::env::var("PYO3_TESTING_ISOLATED").unwrap();
+    assert!(testpath.starts_with("test_params_isolated::case_"));
+    assert_eq!(input.len(), 1);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3params(
+    input, expected;
+    (1, 2), #[should_panic(expected = "assertion `left == right` failed")] wrong: (2, 4)
+)]
+fn test_params_fail_individually() {
+    let result: isize = addone!(input);
+    assert_eq!(result, expected);
+}
+
 #[test]
 fn test_compile_errors_pyo3testing() {
     let t = trybuild::TestCases::new();
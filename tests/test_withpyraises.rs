@@ -1,4 +1,7 @@
-use pyo3::{exceptions::PyTypeError, prelude::*};
+use pyo3::{
+    exceptions::{PyAttributeError, PyTypeError, PyValueError, PyZeroDivisionError},
+    prelude::*,
+};
 use pyo3_testing::{pyo3test, with_py_raises};
 
 // The example from the Guide ...
@@ -12,10 +15,24 @@ fn py_addone(num: isize) -> isize {
     o3_addone(num)
 }
 
+#[pyfunction]
+#[pyo3(name = "divide")]
+fn py_divide(py: Python<'_>, num: isize, by: isize) -> PyResult<isize> {
+    match num.checked_div(by) {
+        Some(result) => Ok(result),
+        None => {
+            let error = PyValueError::new_err(format!("cannot divide {num} by {by}"));
+            error.set_cause(py, Some(PyZeroDivisionError::new_err("division by zero")));
+            Err(error)
+        }
+    }
+}
+
 #[pymodule]
 #[pyo3(name = "adders")]
 fn py_adders(module: &Bound<'_, PyModule>) -> PyResult<()> {
     module.add_function(wrap_pyfunction!(py_addone, module)?)?;
+    module.add_function(wrap_pyfunction!(py_divide, module)?)?;
     Ok(())
 }
 
@@ -35,7 +52,156 @@ fn test_raises_validate_approach() {
 #[allow(unused_macros)]
 #[pyo3import(py_adders: from adders import addone)]
 fn test_raises() {
-    with_py_raises!(PyTypeError, { addone.call1(("4",)) }); //can't use `let result =` here
+    with_py_raises!(PyTypeError, { addone.call1(("4",)) });
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_raises_call_macro() {
+    with_py_raises!(PyTypeError, { addone!("4") });
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import divide)]
+fn test_raises_named_python_token(gil: Python<'_>) {
+    let error = with_py_raises!(PyValueError, match = "divide", { divide!(1, 0) });
+    assert!(error.is_instance_of::<PyValueError>(gil));
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import *)]
+fn test_raises_namespace_call_macro() {
+    with_py_raises!(PyTypeError, { adders_ns!(addone("4")) });
+    with_py_raises!(PyAttributeError, { adders_ns!(subtract(1)) });
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_raises_call_macro_in_longer_block() {
+    with_py_raises!(PyTypeError, {
+        let arg = "4";
+        addone!(arg)
+    });
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_raises_then_continue() {
+    with_py_raises!(PyTypeError, { addone!("4") });
+    with_py_raises!(PyTypeError, { addone!(1, 2) });
+    with_py_raises!(PyTypeError, { addone!() });
+    let result: isize = addone!(1);
+    assert_eq!(result, 2);
+}
+
+#[pyo3test]
+#[should_panic(expected = "continued after with_py_raises")]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_raises_does_not_return() {
+    with_py_raises!(PyTypeError, { addone!("4") });
+    panic!("continued after with_py_raises");
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import divide)]
+fn test_raises_returns_error() {
+    let error = with_py_raises!(PyValueError, { divide!(1, 0) });
+    assert_eq!(error.value(py).to_string(), "cannot divide 1 by 0");
+    let args: (String,) = error.value(py).getattr("args").unwrap().extract().unwrap();
+    assert_eq!(args, ("cannot divide 1 by 0".to_string(),));
+    assert!(error
+        .cause(py)
+        .unwrap()
+        .is_instance_of::<PyZeroDivisionError>(py));
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import divide)]
+fn test_raises_match() {
+    with_py_raises!(PyValueError, match = "cannot divide", { divide!(1, 0) });
+    with_py_raises!(PyValueError, match = r"^cannot divide \d+ by 0$", { divide!(1, 0) });
+}
+
+/// The example from the docs of `with_py_raises!`
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_raises_with_message() {
+    with_py_raises!(PyTypeError, match = "cannot be interpreted as an integer", { addone!("4") });
+}
+
+#[pyo3test]
+#[should_panic(
+    expected = "Regex pattern did not match the exception message:\n- by zero\n+ cannot divide 1 by 0"
+)]
+#[pyo3import(py_adders: from adders import divide)]
+fn test_raises_match_fails() {
+    with_py_raises!(PyValueError, match = "by zero", { divide!(1, 0) });
+}
+
+#[pyo3test]
+#[should_panic(expected = "Invalid regex pattern \"divide(1\", use `\\` to escape")]
+#[pyo3import(py_adders: from adders import divide)]
+fn test_raises_match_invalid_regex() {
+    with_py_raises!(PyValueError, match = "divide(1", { divide!(1, 0) });
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3import(py_adders: from adders import divide)]
+fn test_raises_paths_and_tuples() {
+    with_py_raises!(pyo3::exceptions::PyTypeError, { addone!("4") });
+    with_py_raises!((PyTypeError, PyValueError), { addone!("4") });
+    with_py_raises!((PyTypeError, PyValueError), { divide!(1, 0) });
+}
+
+#[pyo3test]
+#[should_panic(expected = "Wrong Error: expected PyTypeError but got <class 'ValueError'>")]
+#[pyo3import(py_adders: from adders import divide)]
+fn test_wrong_error_message() {
+    with_py_raises!(PyTypeError, { divide!(1, 0) });
+}
+
+#[pyo3test]
+#[should_panic(
+    expected = "ZeroDivisionError: division by zero\n\nThe above exception \
+    was the direct cause of the following exception:\n\nValueError: cannot divide 1 by 0"
+)]
+#[pyo3import(py_adders: from adders import divide)]
+fn test_wrong_error_traceback() {
+    with_py_raises!(PyTypeError, { divide!(1, 0) });
+}
+
+#[pyo3test]
+#[should_panic(
+    expected = "No Error: expected PyTypeError or PyValueError but the block returned 2"
+)]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_no_error_message() {
+    with_py_raises!((PyTypeError, PyValueError), { addone!(1) });
+}
+
+#[pyo3test]
+#[should_panic(expected = "No Error: expected PyTypeError but the block returned 2")]
+#[pyo3import(py_adders: from adders import addone)]
+fn test_no_error_message_without_call_macro() {
+    with_py_raises!(PyTypeError, { addone.call1((1,)) });
+}
+
+#[pyo3test]
+#[should_panic(expected = "No Error: expected PyTypeError but the block returned 'two'")]
+fn test_no_error_message_rust_value() {
+    with_py_raises!(PyTypeError, { PyResult::Ok("two".to_string()) });
+}
+
+// The block's value does not need to implement `Debug`
+struct NotDebug;
+
+#[pyo3test]
+#[should_panic(
+    expected = "No Error: expected PyTypeError but the block returned a test_withpyraises::NotDebug"
+)]
+fn test_no_error_message_not_debug() {
+    with_py_raises!(PyTypeError, { PyResult::Ok(NotDebug) });
 }
 
 #[test]
@@ -0,0 +1,25 @@
+use pyo3::prelude::*;
+use pyo3_testing::pyo3fixture;
+
+#[pyo3fixture(scope = "module")]
+fn with_options(py: Python<'_>) -> Bound<'_, PyAny> {
+    py.None().into_bound(py)
+}
+
+#[pyo3fixture]
+fn two_python_tokens(py: Python<'_>, gil: Python<'_>) -> Bound<'_, PyAny> {
+    py.None().into_bound(py)
+}
+
+#[pyo3fixture]
+fn argument_not_an_identifier(py: Python<'_>, (left, right): (isize, isize)) -> isize {
+    left + right
+}
+
+// This passes without error ... for copy-pasting ;)
+#[pyo3fixture]
+fn good_example(py: Python<'_>) -> Bound<'_, PyAny> {
+    py.None().into_bound(py)
+}
+
+fn main() {}
@@ -34,6 +34,48 @@ fn no_colon() {}
 #[pyo3import(py_adders: from adders import)]
 fn missing_function() {}
 
+#[pyo3test]
+#[pyo3import(py_adders: from adders import type)]
+fn keyword_without_alias() {}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone as)]
+fn missing_alias() {}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import ())]
+fn empty_brackets() {}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone,)]
+fn trailing_comma_without_brackets() {}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders. import addone)]
+fn missing_submodule() {}
+
+#[pyo3test]
+#[pyo3import(py_adders: import adders.type)]
+fn submodule_keyword_without_alias() {}
+
+#[pyo3test]
+#[pyo3import(file "tests/py/helpers.py" from helpers import apply)]
+fn file_without_colon() {}
+
+#[pyo3test]
+#[pyo3code(module = "helpers", "def two(): return 2")]
+fn code_without_name() {}
+
+#[pyo3test]
+#[pyo3code(name = "helpers")]
+fn code_without_code() {}
+
+#[pyo3test]
+fn two_python_tokens(py: Python<'_>, gil: Python<'_>) {}
+
+#[pyo3test]
+fn argument_not_an_identifier((left, right): (isize, isize)) {}
+
 // This will compile fine with trybuild due to the #[test] which is added to the
 // wrapped function. see https://github.com/dtolnay/trybuild/issues/231
 //
@@ -47,6 +89,46 @@ fn missing_function() {}
 #[pyo3import(py_addrs: from adders import addone)]
 fn invalid_rust_method() {}
 
+#[pyo3test(isolate)]
+fn unknown_option() {}
+
+#[pyo3test(isolated, twice)]
+fn extra_option() {}
+
+#[pyo3test]
+#[pyo3params(input, expected; (1, 2), ("x", err PyTypeError))]
+fn params_err_returns_other() -> Result<(), String> {
+    Ok(())
+}
+
+#[pyo3test]
+#[pyo3params(input, expected; (1, 2), ("x", err PyTypeError))]
+fn params_err_cannot_raise() {
+    assert_eq!(input + 1, expected);
+}
+
+#[pyo3test]
+#[pyo3import(py_adders: from adders import addone)]
+#[pyo3params(input, expected; (1, 2), ("x", err PyTypeError))]
+fn params_err_used_before_raising() {
+    let result: isize = addone!(input);
+    assert_eq!(result, expected);
+    let _: isize = addone!(expected);
+}
+
+#[pyo3test]
+#[pyo3params(input, expected; (1, 2), (3))]
+fn params_missing_value() {}
+
+#[pyo3test]
+#[pyo3params(input, expected (1, 2))]
+fn params_without_semicolon() {}
+
+#[pyo3test]
+#[pyo3params(input; 1)]
+#[pyo3params(expected; 2)]
+fn params_twice() {}
+
 // This passes without error ... for copy-pasting ;)
 #[pyo3test]
 #[pyo3import(py_adders: from adders import addone)]
@@ -4,8 +4,32 @@ fn test_missing_comma() {
     with_py_raises!(PyTypeError { () });
 }
 
+fn test_missing_comma_after_path() {
+    with_py_raises!(pyo3::exceptions::PyTypeError { () });
+}
+
+fn test_missing_comma_after_tuple() {
+    with_py_raises!((PyTypeError, PyValueError) { () });
+}
+
 fn test_missing_braces() {
     with_py_raises!(PyTypeError, Ok(()));
 }
 
+fn test_missing_match_equals() {
+    with_py_raises!(PyTypeError, match "regex", { Ok(()) });
+}
+
+fn test_match_not_a_string() {
+    with_py_raises!(PyTypeError, match = regex, { Ok(()) });
+}
+
+fn test_missing_match_comma() {
+    with_py_raises!(PyTypeError, match = "regex" { Ok(()) });
+}
+
+fn test_empty_error_tuple() {
+    with_py_raises!((), { Ok(()) });
+}
+
 fn main() {}